@@ -4,12 +4,15 @@ use std::rc::Rc;
 
 pub(crate) struct ActionSetIndex {
     raw_tree: Trie<String, Vec<Rc<RuntimeActionSet>>>,
+    // Selected by their predicates alone, when none of the host's action sets apply
+    host_agnostic: Vec<Rc<RuntimeActionSet>>,
 }
 
 impl ActionSetIndex {
     pub fn new() -> Self {
         Self {
             raw_tree: Trie::new(),
+            host_agnostic: Vec::new(),
         }
     }
 
@@ -24,6 +27,10 @@ impl ActionSetIndex {
         );
     }
 
+    pub fn insert_host_agnostic(&mut self, action_set: Rc<RuntimeActionSet>) {
+        self.host_agnostic.push(action_set);
+    }
+
     pub fn get_longest_match_action_sets(
         &self,
         subdomain: &str,
@@ -32,8 +39,13 @@ impl ActionSetIndex {
         self.raw_tree.get_ancestor_value(&rev)
     }
 
+    pub fn get_host_agnostic_action_sets(&self) -> &[Rc<RuntimeActionSet>] {
+        &self.host_agnostic
+    }
+
+    // Host names are case-insensitive, so are both the configured ones and the requests'
     fn reverse_subdomain(subdomain: &str) -> String {
-        let mut s = subdomain.to_string();
+        let mut s = subdomain.to_ascii_lowercase();
         s.push('.');
         if s.starts_with('*') {
             s.remove(0);
@@ -47,15 +59,15 @@ impl ActionSetIndex {
 #[cfg(test)]
 mod tests {
     use crate::action_set_index::ActionSetIndex;
+    use crate::configuration::ActionSet;
     use crate::runtime_action_set::RuntimeActionSet;
+    use std::collections::HashMap;
     use std::rc::Rc;
 
     fn build_ratelimit_action_set(name: &str) -> RuntimeActionSet {
-        RuntimeActionSet {
-            name: name.to_owned(),
-            route_rule_predicates: Default::default(),
-            runtime_actions: Vec::new(),
-        }
+        let action_set = ActionSet::new(name.to_owned(), Default::default(), Vec::new());
+        RuntimeActionSet::new(&action_set, &HashMap::default())
+            .expect("should not happen from an empty set of actions")
     }
 
     #[test]
@@ -127,4 +139,41 @@ mod tests {
         assert!(val.is_some());
         assert_eq!(val.expect("value must be some")[0].name, "rlp1");
     }
+
+    #[test]
+    fn hosts_match_whatever_their_case() {
+        let mut index = ActionSetIndex::new();
+        let rlp1 = build_ratelimit_action_set("rlp1");
+        index.insert("*.toystore.com", Rc::new(rlp1));
+        let rlp2 = build_ratelimit_action_set("rlp2");
+        index.insert("Example.COM", Rc::new(rlp2));
+
+        let val = index.get_longest_match_action_sets("CARS.TOYSTORE.COM");
+        assert!(val.is_some());
+        assert_eq!(val.expect("value must be some")[0].name, "rlp1");
+
+        let val = index.get_longest_match_action_sets("example.com");
+        assert!(val.is_some());
+        assert_eq!(val.expect("value must be some")[0].name, "rlp2");
+
+        // still only subdomains for wildcards
+        let val = index.get_longest_match_action_sets("TOYSTORE.COM");
+        assert!(val.is_none());
+        let val = index.get_longest_match_action_sets("test.EXAMPLE.com");
+        assert!(val.is_none());
+    }
+
+    #[test]
+    fn host_agnostic_action_sets_are_kept_apart() {
+        let mut index = ActionSetIndex::new();
+        let rlp1 = build_ratelimit_action_set("rlp1");
+        index.insert_host_agnostic(Rc::new(rlp1));
+
+        let val = index.get_longest_match_action_sets("example.com");
+        assert!(val.is_none());
+
+        let val = index.get_host_agnostic_action_sets();
+        assert_eq!(val.len(), 1);
+        assert_eq!(val[0].name, "rlp1");
+    }
 }
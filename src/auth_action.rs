@@ -1,7 +1,7 @@
-use crate::configuration::{Action, FailureMode, Service};
+use crate::configuration::{Action, EnforcementMode, FailureMode, Service};
 use crate::data::{store_metadata, Predicate, PredicateVec};
 use crate::envoy::{CheckResponse, CheckResponse_oneof_http_response, HeaderValueOption};
-use crate::service::{GrpcErrResponse, GrpcService, Headers};
+use crate::service::{DenyReply, GrpcErrResponse, GrpcService, Headers};
 use log::debug;
 use std::rc::Rc;
 
@@ -10,10 +10,41 @@ pub struct AuthAction {
     grpc_service: Rc<GrpcService>,
     scope: String,
     predicates: Vec<Predicate>,
+    deny_response: Option<DenyReply>,
 }
 
 impl AuthAction {
     pub fn new(action: &Action, service: &Service) -> Result<Self, String> {
+        if !action.response_predicates.is_empty()
+            || action.on_response_body
+            || action.on_response_trailers
+        {
+            return Err(format!(
+                "Auth action {} can't be deferred to the response",
+                action.scope
+            ));
+        }
+        if action.scope_expression.is_some() {
+            return Err(format!(
+                "Auth action {} can't have a scope expression",
+                action.scope
+            ));
+        }
+        if action.enforcement_mode != EnforcementMode::Enforce {
+            return Err(format!("Auth action {} can't be a dry run", action.scope));
+        }
+        if action.response_headers.is_some() || action.rate_limit_headers_prefix.is_some() {
+            return Err(format!(
+                "Auth action {} can't filter nor rename the response headers",
+                action.scope
+            ));
+        }
+        if action.decision_cache.is_some() {
+            return Err(format!(
+                "Auth action {} can't cache its decisions",
+                action.scope
+            ));
+        }
         let mut predicates = Vec::default();
         for predicate in &action.predicates {
             predicates.push(Predicate::new(predicate).map_err(|e| e.to_string())?);
@@ -23,6 +54,11 @@ impl AuthAction {
             grpc_service: Rc::new(GrpcService::new(Rc::new(service.clone()))),
             scope: action.scope.clone(),
             predicates,
+            deny_response: action
+                .deny_response
+                .as_ref()
+                .map(DenyReply::new)
+                .transpose()?,
         })
     }
 
@@ -60,17 +96,26 @@ impl AuthAction {
                         debug!("process_response(auth): continuing as FailureMode Allow");
                         Ok(Vec::default())
                     }
+                    FailureMode::Passthrough => {
+                        debug!("process_response(auth): continuing as FailureMode Passthrough");
+                        Ok(self.grpc_service.degraded_headers())
+                    }
                 }
             }
             Some(CheckResponse_oneof_http_response::denied_response(denied_response)) => {
                 debug!("process_response(auth): received DeniedHttpResponse");
                 let status_code = denied_response.get_status().get_code();
                 let response_headers = Self::get_header_vec(denied_response.get_headers());
-                Err(GrpcErrResponse::new(
-                    status_code as u32,
-                    response_headers,
-                    denied_response.body,
-                ))
+                match &self.deny_response {
+                    Some(deny_response) => {
+                        Err(GrpcErrResponse::new_denied(deny_response, response_headers))
+                    }
+                    None => Err(GrpcErrResponse::new(
+                        status_code as u32,
+                        response_headers,
+                        denied_response.body,
+                    )),
+                }
             }
             Some(CheckResponse_oneof_http_response::ok_response(ok_response)) => {
                 debug!("process_response(auth): received OkHttpResponse");
@@ -106,7 +151,7 @@ impl AuthAction {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::configuration::{Action, FailureMode, Service, ServiceType, Timeout};
+    use crate::configuration::{Action, FailureMode, ResetFormat, Service, ServiceType, Timeout};
     use crate::envoy::{DeniedHttpResponse, HeaderValue, HttpStatus, OkHttpResponse, StatusCode};
     use protobuf::RepeatedField;
 
@@ -121,15 +166,34 @@ mod test {
         let action = Action {
             service: "some_service".into(),
             scope: "some_scope".into(),
+            scope_expression: None,
             predicates,
             data: Vec::default(),
+            tenant_scope: None,
+            read_write_domains: false,
+            response_predicates: Vec::default(),
+            enforcement_mode: EnforcementMode::default(),
+            response_headers: None,
+            rate_limit_headers_prefix: None,
+            on_response_body: false,
+            on_response_trailers: false,
+            failure_mode: None,
+            timeout: None,
+            rate_limit_reset_format: ResetFormat::default(),
+            deny_response: None,
+            decision_cache: None,
         };
 
         let service = Service {
             service_type: ServiceType::Auth,
             endpoint: "some_endpoint".into(),
-            failure_mode,
+            failure_mode: Some(failure_mode),
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         };
 
         AuthAction::new(&action, &service)
@@ -315,4 +379,21 @@ mod test {
         let headers = result.expect("is ok");
         assert!(headers.is_empty());
     }
+
+    #[test]
+    fn process_error_response_as_passthrough() {
+        let auth_action = build_auth_action_with_predicates_and_failure_mode(
+            Vec::default(),
+            FailureMode::Passthrough,
+        );
+        let error_response = build_check_response(StatusCode::InternalServerError, None, None);
+        let result = auth_action.process_response(error_response);
+        assert!(result.is_ok());
+
+        let headers = result.expect("is ok");
+        assert_eq!(
+            headers,
+            vec![("x-kuadrant-degraded".to_string(), "true".to_string())]
+        );
+    }
 }
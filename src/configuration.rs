@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Formatter};
 use std::sync::Arc;
 
@@ -13,17 +13,141 @@ use std::time::Duration;
 pub struct Action {
     pub service: String,
     pub scope: String,
+    // Rate limiting only: expression resolving to the scope of each request, e.g. per API
+    // version, used in place of the literal `scope` for the domain
+    pub scope_expression: Option<String>,
     #[serde(default)]
     pub predicates: Vec<String>,
     #[serde(default)]
     pub data: Vec<DataItem>,
+    pub tenant_scope: Option<TenantScope>,
+    // Suffixes the domain with `:read` or `:write`, so both get their own budget
+    #[serde(default)]
+    pub read_write_domains: bool,
+    // Rate limiting only: defers the action to the response, only counting the requests whose
+    // response matches, e.g. `response.code >= 500`
+    #[serde(default)]
+    pub response_predicates: Vec<String>,
+    // Rate limiting only: `dryRun` still calls the service, but lets the requests over the
+    // limit through, counting them in `kuadrant.denied_shadow`, e.g. to roll out new limits
+    #[serde(default)]
+    pub enforcement_mode: EnforcementMode,
+    // Rate limiting only: which of the headers the service adds, e.g. `x-ratelimit-remaining`,
+    // make it to the response, all of them unless set
+    pub response_headers: Option<HeaderFilter>,
+    // Rate limiting only: replaces the `X-RateLimit-` or `RateLimit-` prefix of the headers the
+    // service adds, whatever its casing, e.g. `RateLimit-` for the IETF draft's naming
+    pub rate_limit_headers_prefix: Option<String>,
+    // Rate limiting only: defers the action to the end of the response body, i.e. its last
+    // chunk or its trailers if any, e.g. to count the bytes served with `response.body_size`.
+    // The response headers are sent by then, so none are added.
+    #[serde(default)]
+    pub on_response_body: bool,
+    // Rate limiting only: defers the action to the response trailers, e.g. to build descriptors
+    // from `responseTrailer('x-usage-tokens')`, as per the trailers of the response. They end
+    // its body, so it runs along with the actions awaiting it, but not for responses without.
+    #[serde(default)]
+    pub on_response_trailers: bool,
+    // Overrides the failure mode of the service, for this action only
+    pub failure_mode: Option<FailureMode>,
+    // Overrides the timeout of the service, for this action only, e.g. for slower auth configs
+    pub timeout: Option<Timeout>,
+    // Rate limiting only: whether the `RateLimit-Reset` headers the service adds tell the
+    // seconds until the reset, as the service does, or when it happens, since the epoch
+    #[serde(default)]
+    pub rate_limit_reset_format: ResetFormat,
+    // Replaces the reply to the requests the service denies, e.g. over the limit, the action
+    // set's applying unless set
+    pub deny_response: Option<DenyResponse>,
+    // Rate limiting only: identical requests, i.e. of the same domain and descriptors, found
+    // over the limit are denied locally for as long as the TTL, sparing the service the calls
+    pub decision_cache: Option<DecisionCacheConfig>,
+}
+
+// Header names, matched case-insensitively, either the only ones let through or the ones left out
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderFilter {
+    Allow(Vec<String>),
+    Deny(Vec<String>),
+}
+
+impl HeaderFilter {
+    pub fn lets_through(&self, header: &str) -> bool {
+        match self {
+            Self::Allow(allowed) => allowed.iter().any(|h| h.eq_ignore_ascii_case(header)),
+            Self::Deny(denied) => !denied.iter().any(|h| h.eq_ignore_ascii_case(header)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum EnforcementMode {
+    #[default]
+    Enforce,
+    DryRun,
+}
+
+// Only the denials are cached, as the requests let through locally wouldn't be counted
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DecisionCacheConfig {
+    pub ttl: Timeout,
+    // Requests whose decision is cached, past which the least recently used one is evicted
+    pub capacity: Option<usize>,
+}
+
+// Reply to the denied requests, in place of the service's, the headers it adds included but for
+// those set here
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DenyResponse {
+    pub status: u32,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // CEL expression evaluated when replying, in place of the body, e.g.
+    // `'Denied, see request ' + request.id`. Only the expressions see the request's attributes, so
+    // none make it to the reply unless opted in here.
+    pub body_expression: Option<String>,
+    // Same as `bodyExpression`, for the headers of the same name
+    #[serde(default)]
+    pub header_expressions: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetFormat {
+    #[default]
+    Delta,
+    Epoch,
+}
+
+// Scopes rate limiting to a tenant, the domain becoming `{scope}/{tenant}`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantScope {
+    // Expression resolving to the tenant, e.g. `auth.identity.tenant`
+    pub expression: String,
+    // Regular expression the whole tenant value must match for the request to be let through
+    pub allowed_pattern: String,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct RouteRuleConditions {
-    pub hostnames: Vec<String>,
+    // Unset means any host, leaving the selection to the predicates, while an empty list matches
+    // none
+    pub hostnames: Option<Vec<String>>,
     #[serde(default)]
     pub predicates: Vec<String>,
+    // Requests whose headers add up to more bytes don't match, see `request.header_size`
+    #[serde(rename = "maxHeaderSize")]
+    pub max_header_size: Option<u64>,
+    // WebSocket handshakes don't match, e.g. for limits meant for plain requests only, see
+    // `request.websocket_upgrade`
+    #[serde(rename = "skipWebsocketUpgrades", default)]
+    pub skip_websocket_upgrades: bool,
 }
 
 #[derive(Default, Deserialize, Debug, Clone)]
@@ -32,6 +156,53 @@ pub struct ActionSet {
     pub name: String,
     pub route_rule_conditions: RouteRuleConditions,
     pub actions: Vec<Action>,
+    pub mtls: Option<MtlsRequirement>,
+    pub sni_match: Option<SniMatchRequirement>,
+    // Requests in flight for the action set, past which further ones are denied
+    pub max_concurrency: Option<u32>,
+    // Requests a single downstream connection can make, past which further ones are denied. Only
+    // the 1024 most recently active connections are counted, see `ConnectionCounter`
+    pub max_requests_per_connection: Option<u32>,
+    // Failure mode of the actions whose service doesn't set one
+    pub failure_mode: Option<FailureMode>,
+    // Reply to the requests denied by the actions not setting one
+    pub deny_response: Option<DenyResponse>,
+}
+
+// Denies the requests not made over mTLS, before any action is run
+#[derive(Deserialize, Debug, Clone)]
+pub struct MtlsRequirement {
+    #[serde(default = "MtlsRequirement::default_status")]
+    pub status: u32,
+}
+
+impl MtlsRequirement {
+    // nginx's "SSL Certificate Required"
+    fn default_status() -> u32 {
+        496
+    }
+}
+
+// Denies the requests whose `Host` isn't the TLS SNI, i.e. domain fronted, before any action is run
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SniMatchRequirement {
+    #[serde(default = "SniMatchRequirement::default_status")]
+    pub status: u32,
+    // Whether the requests without SNI, e.g. not over TLS, are let through
+    #[serde(default = "SniMatchRequirement::default_allow_without_sni")]
+    pub allow_without_sni: bool,
+}
+
+impl SniMatchRequirement {
+    // "Misdirected Request"
+    fn default_status() -> u32 {
+        421
+    }
+
+    fn default_allow_without_sni() -> bool {
+        true
+    }
 }
 
 impl ActionSet {
@@ -45,6 +216,12 @@ impl ActionSet {
             name,
             route_rule_conditions,
             actions,
+            mtls: None,
+            sni_match: None,
+            max_concurrency: None,
+            max_requests_per_connection: None,
+            failure_mode: None,
+            deny_response: None,
         }
     }
 }
@@ -82,6 +259,7 @@ pub enum FailureMode {
     #[default]
     Deny,
     Allow,
+    Passthrough,
 }
 
 #[derive(Deserialize, Debug, Clone, Default, PartialEq)]
@@ -97,6 +275,60 @@ pub enum ServiceType {
 pub struct PluginConfiguration {
     pub services: HashMap<String, Service>,
     pub action_sets: Vec<ActionSet>,
+    // Response header to write the selected action set and its decision to, for access logs
+    pub decision_header: Option<String>,
+    // Debugging only, never enable in production: exposes why an action failed in the
+    // `x-kuadrant-error` response header
+    #[serde(default)]
+    pub expose_errors: bool,
+    // Debugging only, never enable in production: requests with `x-kuadrant-explain: true` are
+    // replied to with how they would be handled, instead of being proxied
+    #[serde(default)]
+    pub explain: bool,
+    pub tracing: Option<Tracing>,
+    // Only when behind a proxy that sets it: `request.scheme` is taken from `X-Forwarded-Proto`
+    #[serde(default)]
+    pub trust_forwarded_proto: bool,
+    // Where the proxy in front provides the client's geolocation, none is bundled
+    #[serde(default)]
+    pub geo: GeoSources,
+    // Budget of the whole request, what's left of it is propagated to the services as
+    // `grpc-timeout` and caps their calls
+    pub request_deadline: Option<Timeout>,
+    // Records how long the services take to respond, in the `kuadrant.grpc_duration_ms`
+    // histogram, per service type
+    #[serde(default)]
+    pub grpc_duration_metrics: bool,
+    // Actions a single action set can have, past which the configuration is rejected
+    pub max_actions: Option<usize>,
+    // Failure mode of the actions whose service and action set don't set one, `deny` unless set
+    pub failure_mode: Option<FailureMode>,
+    // Whether only the first action set matching a request runs, or all of them in sequence
+    // until one denies it, e.g. a global limit and a per route one
+    #[serde(default)]
+    pub selection_mode: SelectionMode,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionMode {
+    #[default]
+    First,
+    All,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GeoSources {
+    // Property the country is resolved from, e.g. `request.headers.x-geo-country`
+    pub country: Option<String>,
+    // Property the autonomous system number is resolved from
+    pub asn: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Tracing {
+    // Predicate a request must match for its trace context to be propagated to the services
+    pub when: String,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -106,9 +338,46 @@ pub struct Service {
     pub service_type: ServiceType,
     pub endpoint: String,
     // Deny/Allow request when faced with an irrecoverable failure.
-    pub failure_mode: FailureMode,
+    // Passthrough allows it too, but flags the response as having bypassed enforcement.
+    // Overridden by the action's, and the action set's or the global one applies unless set.
+    pub failure_mode: Option<FailureMode>,
+    // Passthrough only: the header flagging the response, `x-kuadrant-degraded` unless set
+    pub degraded_header: Option<String>,
     #[serde(default)]
     pub timeout: Timeout,
+    // How to handle the service timing out, as per the failure mode unless set
+    pub on_timeout: Option<OnTimeout>,
+    // Responses of more bytes fail the call as per the failure mode, rather than being read
+    pub max_response_size: Option<usize>,
+    // Auth only: the attributes of the request sent in the `CheckRequest`, all of them unless set
+    pub field_mask: Option<Vec<CheckRequestField>>,
+    // Times a call failing with a transient status, e.g. `UNAVAILABLE`, is sent again right away
+    // before falling back to the failure mode
+    pub retries: Option<u32>,
+}
+
+// Attributes of the request that can be left out of the `CheckRequest`, sparing their lookup
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckRequestField {
+    Host,
+    Method,
+    Scheme,
+    Path,
+    Protocol,
+    Headers,
+    Time,
+    Source,
+    Destination,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTimeout {
+    Deny,
+    Allow,
+    // Sends the request once more, falling back to the failure mode if that times out too
+    Retry,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -175,6 +444,12 @@ mod test {
                 "endpoint": "limitador-cluster",
                 "failureMode": "allow",
                 "timeout": "42ms"
+            },
+            "limitador-passthrough": {
+                "type": "ratelimit",
+                "endpoint": "limitador-cluster",
+                "failureMode": "passthrough",
+                "degradedHeader": "x-ratelimit-skipped"
             }
         },
         "actionSets": [
@@ -196,6 +471,10 @@ mod test {
             {
                 "service": "limitador",
                 "scope": "rlp-ns-A/rlp-name-A",
+                "tenantScope": {
+                    "expression": "auth.identity.tenant",
+                    "allowedPattern": "[a-z0-9-]+"
+                },
                 "predicates": [
                     "auth.metadata.username == 'alice'"
                 ],
@@ -228,12 +507,12 @@ mod test {
         assert_eq!(plugin_config.action_sets.len(), 1);
 
         let services = &plugin_config.services;
-        assert_eq!(services.len(), 2);
+        assert_eq!(services.len(), 3);
 
         if let Some(auth_service) = services.get("authorino") {
             assert_eq!(auth_service.service_type, ServiceType::Auth);
             assert_eq!(auth_service.endpoint, "authorino-cluster");
-            assert_eq!(auth_service.failure_mode, FailureMode::Deny);
+            assert_eq!(auth_service.failure_mode, Some(FailureMode::Deny));
             assert_eq!(auth_service.timeout, Timeout(Duration::from_millis(24)))
         } else {
             panic!()
@@ -242,12 +521,22 @@ mod test {
         if let Some(rl_service) = services.get("limitador") {
             assert_eq!(rl_service.service_type, ServiceType::RateLimit);
             assert_eq!(rl_service.endpoint, "limitador-cluster");
-            assert_eq!(rl_service.failure_mode, FailureMode::Allow);
+            assert_eq!(rl_service.failure_mode, Some(FailureMode::Allow));
             assert_eq!(rl_service.timeout, Timeout(Duration::from_millis(42)))
         } else {
             panic!()
         }
 
+        if let Some(rl_service) = services.get("limitador-passthrough") {
+            assert_eq!(rl_service.failure_mode, Some(FailureMode::Passthrough));
+            assert_eq!(
+                rl_service.degraded_header.as_deref(),
+                Some("x-ratelimit-skipped")
+            );
+        } else {
+            panic!()
+        }
+
         let predicates = &plugin_config.action_sets[0]
             .route_rule_conditions
             .predicates;
@@ -263,6 +552,14 @@ mod test {
         let rl_action = &actions[1];
         assert_eq!(rl_action.service, "limitador");
         assert_eq!(rl_action.scope, "rlp-ns-A/rlp-name-A");
+        assert_eq!(
+            rl_action.tenant_scope,
+            Some(TenantScope {
+                expression: "auth.identity.tenant".into(),
+                allowed_pattern: "[a-z0-9-]+".into(),
+            })
+        );
+        assert_eq!(auth_action.tenant_scope, None);
 
         let auth_data_items = &auth_action.data;
         assert_eq!(auth_data_items.len(), 0);
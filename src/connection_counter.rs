@@ -0,0 +1,93 @@
+// Requests counted per downstream connection, enforced locally, i.e. per gateway instance.
+//
+// The filter isn't told when a connection closes, only when a request asks for it with
+// `Connection: close`. The other connections are evicted the least recently used first, once as
+// many as the capacity are tracked. Past as many open connections, the count of an evicted one
+// starts over on its next request, i.e. a connection cycling among more than the capacity of
+// active ones gets around the cap.
+use crate::lru::Lru;
+
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub struct ConnectionCounter {
+    // connection id => requests
+    counts: Lru<u64, u32>,
+}
+
+impl ConnectionCounter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            counts: Lru::new(capacity),
+        }
+    }
+
+    /// Counts a request on the connection, returning how many were made on it so far
+    pub fn increment(&mut self, connection_id: u64) -> u32 {
+        match self.counts.get_mut(&connection_id) {
+            Some(count) => {
+                *count = count.saturating_add(1);
+                *count
+            }
+            None => {
+                self.counts.insert(connection_id, 1);
+                1
+            }
+        }
+    }
+
+    /// Stops counting the requests of a closed connection
+    pub fn remove(&mut self, connection_id: u64) {
+        self.counts.remove(&connection_id);
+    }
+
+    #[cfg(test)]
+    pub fn tracked(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+impl Default for ConnectionCounter {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionCounter;
+
+    #[test]
+    fn counts_requests_per_connection() {
+        let mut counter = ConnectionCounter::default();
+        assert_eq!(counter.increment(1), 1);
+        assert_eq!(counter.increment(1), 2);
+        assert_eq!(counter.increment(2), 1);
+        assert_eq!(counter.increment(1), 3);
+        assert_eq!(counter.tracked(), 2);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_connection() {
+        let mut counter = ConnectionCounter::new(2);
+        counter.increment(1);
+        counter.increment(2);
+        counter.increment(1);
+
+        // connection 2 is the least recently used
+        assert_eq!(counter.increment(3), 1);
+        assert_eq!(counter.tracked(), 2);
+        assert_eq!(counter.increment(1), 3);
+        assert_eq!(counter.increment(2), 1);
+    }
+
+    #[test]
+    fn forgets_closed_connections() {
+        let mut counter = ConnectionCounter::default();
+        counter.increment(1);
+        counter.increment(1);
+        counter.remove(1);
+        assert_eq!(counter.tracked(), 0);
+        assert_eq!(counter.increment(1), 1);
+    }
+}
@@ -143,41 +143,61 @@ fn process_metadata(s: &Struct, prefix: String) -> Vec<(String, String)> {
             format!("{prefix}\\.{key}")
         };
 
-        let json: Option<Value> = if value.has_string_value() {
-            Some(value.get_string_value().into())
-        } else if value.has_bool_value() {
-            Some(value.get_bool_value().into())
-        } else if value.has_null_value() {
-            Some(Value::Null)
-        } else if value.has_number_value() {
-            Some(value.get_number_value().into())
-        } else {
-            if !value.has_struct_value() {
-                warn!(
-                    "Don't know how to store Struct field `{}` of kind {:?}",
-                    key, value.kind
-                );
-            }
-            None
-        };
-
         if value.has_struct_value() {
             let nested_struct = value.get_struct_value();
             result.extend(process_metadata(nested_struct, current_prefix));
-        } else if let Some(v) = json {
+        } else if let Some(v) = json_from(value) {
             match serde_json::to_string(&v) {
                 Ok(ser) => result.push((current_prefix, ser)),
                 Err(e) => error!("failed to serialize json Value: {e:?}"),
             }
+        } else {
+            warn!(
+                "Don't know how to store Struct field `{}` of kind {:?}",
+                key, value.kind
+            );
         }
     }
     result
 }
 
+fn json_from(value: &protobuf::well_known_types::Value) -> Option<Value> {
+    if value.has_string_value() {
+        Some(value.get_string_value().into())
+    } else if value.has_bool_value() {
+        Some(value.get_bool_value().into())
+    } else if value.has_null_value() {
+        Some(Value::Null)
+    } else if value.has_number_value() {
+        Some(value.get_number_value().into())
+    } else if value.has_list_value() {
+        Some(Value::Array(
+            value
+                .get_list_value()
+                .get_values()
+                .iter()
+                .filter_map(json_from)
+                .collect(),
+        ))
+    } else if value.has_struct_value() {
+        Some(Value::Object(
+            value
+                .get_struct_value()
+                .get_fields()
+                .iter()
+                .filter_map(|(k, v)| json_from(v).map(|json| (k.clone(), json)))
+                .collect(),
+        ))
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::data::attribute::process_metadata;
-    use protobuf::well_known_types::{Struct, Value, Value_oneof_kind};
+    use protobuf::well_known_types::{ListValue, Struct, Value, Value_oneof_kind};
+    use protobuf::RepeatedField;
     use std::collections::HashMap;
 
     pub fn struct_from(values: Vec<(String, Value)>) -> Struct {
@@ -207,6 +227,18 @@ mod tests {
             cached_size: Default::default(),
         }
     }
+    pub fn list_value_from(values: Vec<Value>) -> Value {
+        Value {
+            kind: Some(Value_oneof_kind::list_value(ListValue {
+                values: RepeatedField::from_vec(values),
+                unknown_fields: Default::default(),
+                cached_size: Default::default(),
+            })),
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        }
+    }
+
     #[test]
     fn get_metadata_one() {
         let metadata = struct_from(vec![(
@@ -261,4 +293,28 @@ mod tests {
         assert!(output.contains(&("identity\\.userid".to_string(), "\"bob\"".to_string())));
         assert!(output.contains(&("other_data".to_string(), "\"other_value\"".to_string())));
     }
+
+    #[test]
+    fn get_metadata_list() {
+        let metadata = struct_from(vec![(
+            "metadata".to_string(),
+            struct_value_from(struct_from(vec![
+                ("tier".to_string(), string_value_from("gold".to_string())),
+                (
+                    "groups".to_string(),
+                    list_value_from(vec![
+                        string_value_from("admin".to_string()),
+                        string_value_from("dev".to_string()),
+                    ]),
+                ),
+            ])),
+        )]);
+        let output = process_metadata(&metadata, String::new());
+        assert_eq!(output.len(), 2);
+        assert!(output.contains(&("metadata\\.tier".to_string(), "\"gold\"".to_string())));
+        assert!(output.contains(&(
+            "metadata\\.groups".to_string(),
+            "[\"admin\",\"dev\"]".to_string()
+        )));
+    }
 }
@@ -9,10 +9,13 @@ use chrono::{DateTime, FixedOffset};
 use log::debug;
 use log::{error, warn};
 use proxy_wasm::types::{Bytes, Status};
+use regex::Regex;
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::sync::{Arc, OnceLock};
 use urlencoding::decode;
@@ -20,6 +23,8 @@ use urlencoding::decode;
 #[derive(Clone, Debug)]
 pub struct Expression {
     attributes: Vec<Attribute>,
+    // paths tested with `has()`, which must be left out when absent rather than bound to null
+    presence_tests: HashSet<Path>,
     expression: CelExpression,
     extended: bool,
 }
@@ -29,7 +34,8 @@ impl Expression {
         let expression = parse(expression)?;
 
         let mut props = Vec::with_capacity(5);
-        properties(&expression, &mut props, &mut Vec::default());
+        let mut tests = Vec::default();
+        properties(&expression, &mut props, &mut tests, &mut Vec::default());
 
         let mut attributes: Vec<Attribute> = props
             .into_iter()
@@ -46,6 +52,7 @@ impl Expression {
 
         Ok(Self {
             attributes,
+            presence_tests: tests.into_iter().map(Path::new).collect(),
             expression,
             extended,
         })
@@ -59,16 +66,45 @@ impl Expression {
         Self::new_expression(expression, true)
     }
 
+    /// Paths referenced by this expression that neither are, nor are nested within, a well known
+    /// attribute. `auth.*` is excluded, as it is only populated at runtime by the auth service.
+    pub fn unknown_attributes(&self) -> Vec<&Path> {
+        self.attributes
+            .iter()
+            .filter(|attr| attr.cel_type.is_none())
+            .map(|attr| &attr.path)
+            .filter(|path| {
+                let tokens = path.tokens();
+                tokens.first() != Some(&"auth")
+                    && !(1..tokens.len())
+                        .any(|i| known_attribute_for(&Path::new(tokens[..i].to_vec())).is_some())
+            })
+            .collect()
+    }
+
     pub fn eval(&self) -> Result<Value, String> {
+        self.eval_with(&HostAttributes)
+    }
+
+    /// Evaluates against the given attributes, e.g. fixed ones rather than the host's. Functions
+    /// reaching for the host, e.g. `getHostProperty`, still do.
+    pub(crate) fn eval_with(&self, attributes: &dyn AttributeResolver) -> Result<Value, String> {
         let mut ctx = create_context();
         if self.extended {
             Self::add_extended_capabilities(&mut ctx)
         }
-        let Map { map } = self.build_data_map();
+        let Map { map } = self.build_data_map(&|attr: &Attribute| attributes.resolve(attr));
 
         ctx.add_function("getHostProperty", get_host_property);
 
-        for binding in ["request", "metadata", "source", "destination", "auth"] {
+        for binding in [
+            "request",
+            "metadata",
+            "source",
+            "destination",
+            "auth",
+            "kuadrant",
+        ] {
             ctx.add_variable_from_value(
                 binding,
                 map.get(&binding.into()).cloned().unwrap_or(Value::Null),
@@ -82,8 +118,29 @@ impl Expression {
         ctx.add_function("queryMap", decode_query_string);
     }
 
-    fn build_data_map(&self) -> Map {
-        data::AttributeMap::new(self.attributes.clone()).into()
+    fn build_data_map(&self, resolve: &dyn Fn(&Attribute) -> Value) -> Map {
+        data::AttributeMap::new(self.attributes.clone(), &self.presence_tests).resolve(resolve)
+    }
+}
+
+/// Where the attributes an expression is evaluated against are resolved from
+pub(crate) trait AttributeResolver {
+    fn resolve(&self, attribute: &Attribute) -> Value;
+}
+
+/// The host's attributes, as the filter evaluates expressions against
+pub(crate) struct HostAttributes;
+
+impl AttributeResolver for HostAttributes {
+    fn resolve(&self, attribute: &Attribute) -> Value {
+        attribute.get()
+    }
+}
+
+/// Fixed attributes, those left out being null
+impl AttributeResolver for HashMap<Path, Value> {
+    fn resolve(&self, attribute: &Attribute) -> Value {
+        self.get(&attribute.path).cloned().unwrap_or(Value::Null)
     }
 }
 
@@ -186,8 +243,217 @@ fn get_host_property(This(this): This<Value>) -> ResolveResult {
     }
 }
 
+/// Returns the value of the first of the named request headers that is present with a
+/// non-empty value, or [`Value::Null`] if none is.
+/// see [`tests::coalesce_headers`]
+fn coalesce_headers(Arguments(args): Arguments) -> ResolveResult {
+    let headers =
+        host_get_map(&"request.headers".into()).map_err(|e| ExecutionError::FunctionError {
+            function: "coalesceHeaders".to_owned(),
+            message: e,
+        })?;
+    for arg in args.iter() {
+        match arg {
+            Value::String(name) => {
+                if let Some((_, value)) = headers
+                    .iter()
+                    .find(|(header, value)| header.eq_ignore_ascii_case(name) && !value.is_empty())
+                {
+                    return Ok(value.clone().into());
+                }
+            }
+            _ => return Err(arg.error_expected_type(ValueType::String)),
+        }
+    }
+    Ok(Value::Null)
+}
+
+// Past as many, the cache is emptied, as patterns may come from the requests themselves
+const MAX_CACHED_PATTERNS: usize = 1024;
+
+thread_local! {
+    // `matchHeader` and `matches` patterns, compiled on first use
+    static PATTERNS: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::default());
+    // distinguishes the `uuid()` evaluations along a request
+    static UUID_SEQUENCE: Cell<u64> = const { Cell::new(0) };
+}
+
+// Applies the pattern, compiled once for all evaluations
+fn with_pattern(
+    function: &str,
+    pattern: &str,
+    f: impl FnOnce(&Regex) -> ResolveResult,
+) -> ResolveResult {
+    PATTERNS.with_borrow_mut(|patterns| {
+        if patterns.len() >= MAX_CACHED_PATTERNS && !patterns.contains_key(pattern) {
+            patterns.clear();
+        }
+        let regex = match patterns.entry(pattern.to_string()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                e.insert(
+                    Regex::new(pattern).map_err(|err| ExecutionError::FunctionError {
+                        function: function.to_owned(),
+                        message: format!("Invalid pattern `{pattern}`: {err}"),
+                    })?,
+                )
+            }
+        };
+        f(regex)
+    })
+}
+
+/// Whether the named request header is present with a value matching the regular expression,
+/// e.g. `matchHeader('user-agent', 'bot|crawler')`. An absent header doesn't match.
+/// see [`tests::match_header`]
+fn match_header(name: Arc<String>, pattern: Arc<String>) -> ResolveResult {
+    with_pattern("matchHeader", &pattern, |regex| {
+        let headers =
+            host_get_map(&"request.headers".into()).map_err(|e| ExecutionError::FunctionError {
+                function: "matchHeader".to_owned(),
+                message: e,
+            })?;
+        Ok(headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(&name))
+            .is_some_and(|(_, value)| regex.is_match(value))
+            .into())
+    })
+}
+
+/// Whether the string matches the (RE2 syntax) regular expression anywhere, unless anchored,
+/// e.g. `matches(request.url_path, '^/v[0-9]+/')` or `request.url_path.matches('^/admin')`.
+/// Unlike the interpreter's own, the pattern is only compiled once.
+/// see [`tests::matches`]
+fn matches(This(target): This<Arc<String>>, pattern: Arc<String>) -> ResolveResult {
+    with_pattern("matches", &pattern, |regex| {
+        Ok(regex.is_match(&target).into())
+    })
+}
+
+/// Returns the value of the named response trailer, e.g. `grpc-status`, or [`Value::Null`] if
+/// absent, which they all are until the response trailers were received, i.e. but for the
+/// actions with `onResponseTrailers`.
+/// see [`tests::response_trailer`]
+fn response_trailer(name: Arc<String>) -> ResolveResult {
+    let trailers =
+        host_get_map(&"response.trailers".into()).map_err(|e| ExecutionError::FunctionError {
+            function: "responseTrailer".to_owned(),
+            message: e,
+        })?;
+    Ok(trailers
+        .into_iter()
+        .find(|(trailer, _)| trailer.eq_ignore_ascii_case(&name))
+        .map(|(_, value)| value.into())
+        .unwrap_or(Value::Null))
+}
+
+/// When the request was received, i.e. `request.time`, rather than the host's clock, so that
+/// it's the same all along the request, e.g. `timestamp(auth.identity.expires_at) > now() +
+/// duration('5m')`. Along with the `duration()` and `timestamp()` conversions, it makes up the
+/// arithmetic on time windows.
+/// see [`tests::now`]
+fn now() -> ResolveResult {
+    let error = |message: String| ExecutionError::FunctionError {
+        function: "now".to_owned(),
+        message,
+    };
+    get_attribute::<DateTime<FixedOffset>>(&"request.time".into())
+        .map_err(error)?
+        .map(Value::Timestamp)
+        .ok_or_else(|| error("request.time is not available".to_owned()))
+}
+
+/// A v4 formatted UUID, e.g. as a correlation id. It's derived from the SHA-256 digest of the
+/// `request.id`, which the host generates randomly, and of a sequence number, as the module has
+/// no randomness of its own to rely on. Unlike any other function, it's not deterministic: every
+/// evaluation yields another one, so the expression should be evaluated once per request, and
+/// never be part of a predicate.
+/// see [`tests::uuid`]
+fn uuid() -> ResolveResult {
+    let error = |message: String| ExecutionError::FunctionError {
+        function: "uuid".to_owned(),
+        message,
+    };
+    let request_id = get_attribute::<String>(&"request.id".into())
+        .map_err(error)?
+        .ok_or_else(|| error("request.id is not available".to_owned()))?;
+    let sequence = UUID_SEQUENCE.with(|sequence| {
+        let next = sequence.get().wrapping_add(1);
+        sequence.set(next);
+        next
+    });
+
+    let mut digest = Sha256::new();
+    digest.update(request_id.as_bytes());
+    digest.update(sequence.to_le_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest.finalize()[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+    .into())
+}
+
+/// The size of a string, in characters rather than bytes, of bytes, or of a list or map, e.g.
+/// `size(request.headers) > 50`. Strings are sized in characters, not in UTF-8 bytes, whatever
+/// the interpreter's own does. As per CEL, it is an `int`, so it compares with literals as is.
+/// see [`tests::size`]
+fn size(This(value): This<Value>) -> ResolveResult {
+    let size = match &value {
+        Value::String(s) => s.chars().count(),
+        Value::Bytes(b) => b.len(),
+        Value::List(l) => l.len(),
+        Value::Map(m) => m.map.len(),
+        _ => {
+            return Err(ExecutionError::FunctionError {
+                function: "size".to_owned(),
+                message: format!("Expects a string, bytes, a list or a map, got `{value:?}`"),
+            })
+        }
+    };
+    Ok(Value::Int(size as i64))
+}
+
 fn create_context<'a>() -> Context<'a> {
     let mut ctx = Context::default();
+    ctx.add_function("authScheme", authorization::auth_scheme);
+    ctx.add_function("bearerToken", authorization::bearer_token);
+    ctx.add_function("coalesceHeaders", coalesce_headers);
+    ctx.add_function("jwtClaim", authorization::jwt_claim);
+    ctx.add_function("matchHeader", match_header);
+    ctx.add_function("matches", matches);
+    ctx.add_function("hostMatchesSni", sni::host_matches_sni);
+    ctx.add_function("inCidr", network::in_cidr);
+    ctx.add_function("portMatchesScheme", network::port_matches_scheme);
+    ctx.add_function("responseTrailer", response_trailer);
+    ctx.add_function("uuid", uuid);
+    ctx.add_function("now", now);
+    ctx.add_function("size", size);
+    ctx.add_function("formatFingerprint", encoding::format_fingerprint);
+    ctx.add_function("base64Encode", encoding::base64_encode);
+    ctx.add_function("base64Decode", encoding::base64_decode);
+    ctx.add_function("stableHash", hashing::stable_hash);
+    ctx.add_function("sha256", hashing::sha256);
+    ctx.add_function("clamp", numbers::clamp);
+    ctx.add_function("min", numbers::min);
+    ctx.add_function("max", numbers::max);
+    ctx.add_function("abs", numbers::abs);
+    ctx.add_function("parseInt", numbers::parse_int);
+    ctx.add_function("parseFloat", numbers::parse_float);
+    ctx.add_function("canonicalHost", strings::canonical_host);
+    ctx.add_function("escapeDescriptor", strings::escape_descriptor);
+    ctx.add_function("withoutQuery", strings::without_query);
+    ctx.add_function("queryOf", strings::query_of);
     ctx.add_function("charAt", strings::char_at);
     ctx.add_function("indexOf", strings::index_of);
     ctx.add_function("join", strings::join);
@@ -197,20 +463,33 @@ fn create_context<'a>() -> Context<'a> {
     ctx.add_function("trim", strings::trim);
     ctx.add_function("replace", strings::replace);
     ctx.add_function("split", strings::split);
+    ctx.add_function("strConcat", strings::str_concat);
+    ctx.add_function("format", strings::format);
+    ctx.add_function("startsWithAny", strings::starts_with_any);
+    ctx.add_function("endsWithAny", strings::ends_with_any);
     ctx.add_function("substring", strings::substring);
+    ctx.add_function("substringBytes", strings::substring_bytes);
     ctx
 }
 
+mod authorization;
+mod encoding;
+mod hashing;
+mod network;
+mod numbers;
+mod sni;
 mod strings;
 
 #[derive(Clone, Debug)]
 pub struct Predicate {
+    source: String,
     expression: Expression,
 }
 
 impl Predicate {
     pub fn new(predicate: &str) -> Result<Self, ParseError> {
         Ok(Self {
+            source: predicate.to_string(),
             expression: Expression::new(predicate)?,
         })
     }
@@ -220,12 +499,28 @@ impl Predicate {
     /// See [`Expression::add_extended_capabilities`]
     pub fn route_rule(predicate: &str) -> Result<Self, ParseError> {
         Ok(Self {
+            source: predicate.to_string(),
             expression: Expression::new_extended(predicate)?,
         })
     }
 
+    /// The CEL source the predicate was compiled from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
     pub fn test(&self) -> Result<bool, String> {
-        match self.expression.eval() {
+        self.evaluate_with(&HostAttributes)
+    }
+
+    /// Tests the predicate against the given attributes, e.g. fixtures of the requests it is
+    /// meant to match rather than the host's, see [`Expression::eval_with`]
+    pub(crate) fn evaluate_with(&self, attributes: &dyn AttributeResolver) -> Result<bool, String> {
+        Self::as_bool(self.expression.eval_with(attributes))
+    }
+
+    fn as_bool(result: Result<Value, String>) -> Result<bool, String> {
+        match result {
             Ok(value) => match value {
                 Value::Bool(result) => Ok(result),
                 _ => Err(format!("Expected boolean value, got {value:?}")),
@@ -332,25 +627,38 @@ pub fn known_attribute_for(path: &Path) -> Option<Attribute> {
 fn json_to_cel(json: &str) -> Value {
     let json_value: Result<JsonValue, _> = serde_json::from_str(json);
     match json_value {
-        Ok(json) => match json {
-            JsonValue::Null => Value::Null,
-            JsonValue::Bool(b) => b.into(),
-            JsonValue::Number(n) => {
-                if n.is_u64() {
-                    n.as_u64().expect("Unreachable: number must be u64").into()
-                } else if n.is_i64() {
-                    n.as_i64().expect("Unreachable: number must be i64").into()
-                } else {
-                    n.as_f64().expect("Unreachable: number must be f64").into()
-                }
-            }
-            JsonValue::String(str) => str.into(),
-            _ => todo!("Need support for more Json!"),
-        },
+        Ok(json) => json_value_to_cel(json),
         _ => json.into(),
     }
 }
 
+fn json_value_to_cel(json: JsonValue) -> Value {
+    match json {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => b.into(),
+        JsonValue::Number(n) => {
+            if n.is_u64() {
+                n.as_u64().expect("Unreachable: number must be u64").into()
+            } else if n.is_i64() {
+                n.as_i64().expect("Unreachable: number must be i64").into()
+            } else {
+                n.as_f64().expect("Unreachable: number must be f64").into()
+            }
+        }
+        JsonValue::String(str) => str.into(),
+        JsonValue::Array(values) => Value::List(Arc::new(
+            values.into_iter().map(json_value_to_cel).collect(),
+        )),
+        JsonValue::Object(map) => {
+            let map: HashMap<Key, Value> = map
+                .into_iter()
+                .map(|(key, value)| (key.into(), json_value_to_cel(value)))
+                .collect();
+            map.into()
+        }
+    }
+}
+
 fn copy(value_type: &ValueType) -> ValueType {
     match value_type {
         ValueType::List => ValueType::List,
@@ -378,7 +686,10 @@ fn new_well_known_attribute_map() -> HashMap<Path, ValueType> {
         ("request.method".into(), ValueType::String),
         ("request.path".into(), ValueType::String),
         ("request.url_path".into(), ValueType::String),
+        ("request.decoded_path".into(), ValueType::String),
         ("request.query".into(), ValueType::String),
+        ("request.content_type".into(), ValueType::String),
+        ("request.fragment".into(), ValueType::String),
         ("request.referer".into(), ValueType::String),
         ("request.size".into(), ValueType::Int),
         ("request.useragent".into(), ValueType::String),
@@ -429,51 +740,81 @@ fn new_well_known_attribute_map() -> HashMap<Path, ValueType> {
         ("connection.id".into(), ValueType::UInt),
         ("ratelimit.hits_addend".into(), ValueType::Int),
         ("request.headers".into(), ValueType::Map),
+        ("response.headers".into(), ValueType::Map),
+        ("response.trailers".into(), ValueType::Map),
+        ("response.code".into(), ValueType::Int),
+        ("response.body_size".into(), ValueType::Int),
         ("request.context_extensions".into(), ValueType::Map),
         ("source.labels".into(), ValueType::Map),
         ("destination.labels".into(), ValueType::Map),
         ("filter_state".into(), ValueType::Map),
         ("connection.mtls".into(), ValueType::Bool),
         ("request.raw_body".into(), ValueType::Bytes),
+        ("kuadrant.trace_key".into(), ValueType::String),
+        ("kuadrant.config_reload_generation".into(), ValueType::Int),
+        ("request.grpc.service".into(), ValueType::String),
+        ("request.grpc.method".into(), ValueType::String),
+        ("request.header_size".into(), ValueType::Int),
+        ("request.forwarded_proto".into(), ValueType::String),
+        ("request.secure".into(), ValueType::Bool),
+        ("request.websocket_upgrade".into(), ValueType::Bool),
+        ("request.geo.country".into(), ValueType::String),
+        ("request.geo.asn".into(), ValueType::String),
     ])
 }
 
-fn properties<'e>(exp: &'e CelExpression, all: &mut Vec<Vec<&'e str>>, path: &mut Vec<&'e str>) {
+fn properties<'e>(
+    exp: &'e CelExpression,
+    all: &mut Vec<Vec<&'e str>>,
+    tests: &mut Vec<Vec<&'e str>>,
+    path: &mut Vec<&'e str>,
+) {
     match exp {
         CelExpression::Arithmetic(e1, _, e2)
         | CelExpression::Relation(e1, _, e2)
         | CelExpression::Ternary(e1, _, e2)
         | CelExpression::Or(e1, e2)
         | CelExpression::And(e1, e2) => {
-            properties(e1, all, path);
-            properties(e2, all, path);
+            properties(e1, all, tests, path);
+            properties(e2, all, tests, path);
         }
         CelExpression::Unary(_, e) => {
-            properties(e, all, path);
+            properties(e, all, tests, path);
         }
         CelExpression::Member(e, a) => {
-            if let Member::Attribute(attr) = &**a {
-                path.insert(0, attr.as_str())
+            match &**a {
+                Member::Attribute(attr) => path.insert(0, attr.as_str()),
+                // an index or fields don't select within the path being built
+                _ => path.clear(),
+            }
+            // neither does anything but another select, e.g. a function's result
+            if !matches!(&**e, CelExpression::Member(..) | CelExpression::Ident(_)) {
+                path.clear();
             }
-            properties(e, all, path);
+            properties(e, all, tests, path);
         }
-        CelExpression::FunctionCall(_, target, args) => {
+        CelExpression::FunctionCall(name, target, args) => {
+            if matches!(&**name, CelExpression::Ident(name) if name.as_str() == "has") {
+                if let Some(tested) = args.first().and_then(selected_path) {
+                    tests.push(tested);
+                }
+            }
             if let Some(target) = target {
-                properties(target, all, path);
+                properties(target, all, tests, path);
             }
             for e in args {
-                properties(e, all, path);
+                properties(e, all, tests, path);
             }
         }
         CelExpression::List(e) => {
             for e in e {
-                properties(e, all, path);
+                properties(e, all, tests, path);
             }
         }
         CelExpression::Map(v) => {
             for (e1, e2) in v {
-                properties(e1, all, path);
-                properties(e2, all, path);
+                properties(e1, all, tests, path);
+                properties(e2, all, tests, path);
             }
         }
         CelExpression::Atom(_) => {}
@@ -487,6 +828,22 @@ fn properties<'e>(exp: &'e CelExpression, all: &mut Vec<Vec<&'e str>>, path: &mu
     }
 }
 
+/// The path of a plain chain of selects, e.g. `auth.identity.username`
+fn selected_path(exp: &CelExpression) -> Option<Vec<&str>> {
+    match exp {
+        CelExpression::Member(e, a) => match &**a {
+            Member::Attribute(attr) => {
+                let mut path = selected_path(e)?;
+                path.push(attr.as_str());
+                Some(path)
+            }
+            _ => None,
+        },
+        CelExpression::Ident(v) => Some(vec![v.as_str()]),
+        _ => None,
+    }
+}
+
 #[cfg(feature = "debug-host-behaviour")]
 pub fn debug_all_well_known_attributes() {
     let attributes = new_well_known_attribute_map();
@@ -505,9 +862,10 @@ pub fn debug_all_well_known_attributes() {
 
 pub mod data {
     use crate::data::cel::Attribute;
+    use crate::data::property::Path;
     use cel_interpreter::objects::{Key, Map};
     use cel_interpreter::Value;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::sync::Arc;
 
     #[derive(Debug)]
@@ -518,10 +876,11 @@ pub mod data {
 
     pub struct AttributeMap {
         data: HashMap<String, Token>,
+        optional: HashSet<Path>,
     }
 
     impl AttributeMap {
-        pub fn new(attributes: Vec<Attribute>) -> Self {
+        pub fn new(attributes: Vec<Attribute>, optional: &HashSet<Path>) -> Self {
             let mut root = HashMap::default();
             for attr in attributes {
                 let mut node = &mut root;
@@ -542,23 +901,41 @@ pub mod data {
                     }
                 }
             }
-            Self { data: root }
+            Self {
+                data: root,
+                optional: optional.clone(),
+            }
+        }
+    }
+
+    impl AttributeMap {
+        /// The map of the attributes' values, as resolved
+        pub fn resolve(self, resolve: &dyn Fn(&Attribute) -> Value) -> Map {
+            map_to_value(self.data, &self.optional, resolve)
         }
     }
 
     impl From<AttributeMap> for Map {
         fn from(value: AttributeMap) -> Self {
-            map_to_value(value.data)
+            value.resolve(&Attribute::get)
         }
     }
 
-    fn map_to_value(map: HashMap<String, Token>) -> Map {
+    fn map_to_value(
+        map: HashMap<String, Token>,
+        optional: &HashSet<Path>,
+        resolve: &dyn Fn(&Attribute) -> Value,
+    ) -> Map {
         let mut out: HashMap<Key, Value> = HashMap::default();
         for (key, value) in map {
             let k = key.into();
             let v = match value {
-                Token::Value(v) => v.get(),
-                Token::Node(map) => Value::Map(map_to_value(map)),
+                Token::Value(v) => match resolve(&v) {
+                    // absent, so that `has()` doesn't find it
+                    Value::Null if optional.contains(&v.path) => continue,
+                    v => v,
+                },
+                Token::Node(map) => Value::Map(map_to_value(map, optional, resolve)),
             };
             out.insert(k, v);
         }
@@ -569,6 +946,7 @@ pub mod data {
     mod tests {
         use crate::data::cel::data::{AttributeMap, Token};
         use crate::data::cel::known_attribute_for;
+        use std::collections::HashSet;
 
         #[test]
         fn it_works() {
@@ -584,6 +962,7 @@ pub mod data {
                         .expect("destination.port known attribute exists"),
                 ]
                 .into(),
+                &HashSet::default(),
             );
 
             println!("{:#?}", map.data);
@@ -639,8 +1018,10 @@ pub mod data {
 mod tests {
     use crate::data::cel::{known_attribute_for, Expression, Predicate};
     use crate::data::property;
+    use crate::data::property::Path;
     use cel_interpreter::objects::ValueType;
     use cel_interpreter::Value;
+    use std::collections::HashMap;
     use std::sync::Arc;
 
     #[test]
@@ -651,6 +1032,44 @@ mod tests {
         assert!(predicate.test().expect("This must evaluate properly!"));
     }
 
+    #[test]
+    fn predicates_evaluate_with_fixtures() {
+        let predicate = Predicate::route_rule(
+            "request.method == 'POST' && request.path.startsWith('/admin') \
+             && (has(auth.identity.tier) ? auth.identity.tier == 'gold' : false) \
+             && request.headers['x-env'] in ['canary', 'staging'] \
+             && queryMap(request.query)['debug'] == 'true'",
+        )
+        .expect("This is valid CEL!");
+
+        let mut attributes: HashMap<Path, Value> = HashMap::from([
+            ("request.method".into(), "POST".into()),
+            ("request.path".into(), "/admin/toy".into()),
+            ("request.query".into(), "debug=true".into()),
+            (
+                "request.headers".into(),
+                Value::Map(HashMap::from([("x-env", "canary")]).into()),
+            ),
+            ("auth.identity.tier".into(), "gold".into()),
+        ]);
+        assert_eq!(predicate.evaluate_with(&attributes), Ok(true));
+
+        attributes.insert("request.method".into(), "GET".into());
+        assert_eq!(predicate.evaluate_with(&attributes), Ok(false));
+
+        attributes.insert("request.method".into(), "POST".into());
+        attributes.remove(&"auth.identity.tier".into());
+        assert_eq!(predicate.evaluate_with(&attributes), Ok(false));
+
+        let e = Expression::new("request.method").expect("This is valid CEL!");
+        assert_eq!(
+            e.eval_with(&HashMap::<Path, Value>::default()),
+            Ok(Value::Null)
+        );
+        let predicate = Predicate::new("request.method").expect("This is valid CEL!");
+        assert!(predicate.evaluate_with(&attributes).is_err());
+    }
+
     #[test]
     fn expressions_sort_properties() {
         let value = Expression::new(
@@ -661,6 +1080,17 @@ mod tests {
         assert_eq!(value.attributes[0].path, "auth.identity".into());
     }
 
+    #[test]
+    fn expressions_list_unknown_attributes() {
+        let value = Expression::new(
+            "request.ur_path == '/' && request.headers.foo == 'bar' && auth.identity.anonymous",
+        )
+        .expect("This is valid CEL!");
+        let unknown = value.unknown_attributes();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(*unknown[0], "request.ur_path".into());
+    }
+
     #[test]
     fn expressions_to_json_resolve() {
         property::test::TEST_PROPERTY_VALUE.set(Some((
@@ -728,6 +1158,121 @@ mod tests {
         assert_eq!(value, "some random crap".into());
     }
 
+    #[test]
+    fn expressions_to_json_collections_resolve() {
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            property::Path::new(vec!["filter_state", "wasm.kuadrant.auth.metadata.groups"]),
+            "[\"admin\",\"dev\"]".bytes().collect(),
+        )));
+        let value = Predicate::new("'dev' in auth.metadata.groups")
+            .expect("This is valid CEL!")
+            .test()
+            .expect("This must evaluate!");
+        assert!(value);
+
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            property::Path::new(vec!["filter_state", "wasm.kuadrant.auth.metadata.plan"]),
+            "{\"tier\":\"gold\",\"quota\":10}".bytes().collect(),
+        )));
+        let value = Predicate::new("auth.metadata.plan['tier'] == 'gold'")
+            .expect("This is valid CEL!")
+            .test()
+            .expect("This must evaluate!");
+        assert!(value);
+    }
+
+    #[test]
+    fn expressions_select_past_function_results() {
+        let value =
+            Expression::new("queryMap(request.query).param1 == 'a' && auth.plan['tier'].id")
+                .expect("This is valid CEL!");
+        assert_eq!(value.attributes.len(), 2);
+        assert_eq!(value.attributes[0].path, "request.query".into());
+        assert_eq!(value.attributes[1].path, "auth.plan".into());
+    }
+
+    #[test]
+    fn has_tests_for_presence() {
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            property::Path::new(vec!["filter_state", "wasm.kuadrant.auth.identity.username"]),
+            "alice".bytes().collect(),
+        )));
+        let predicate = Predicate::new("has(auth.identity.username)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(true));
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some(property::Path::new(vec![
+            "filter_state",
+            "wasm.kuadrant.auth.identity.group",
+        ])));
+        let predicate = Predicate::new("has(auth.identity.group)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(false));
+
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            "request.headers.accept".into(),
+            "*/*".bytes().collect(),
+        )));
+        let predicate = Predicate::new("has(request.headers.accept)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(true));
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some("request.headers.cookie".into()));
+        let predicate = Predicate::new("has(request.headers.cookie)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(false));
+
+        property::test::TEST_PROPERTY_VALUE
+            .set(Some(("request.query".into(), "param1=a".bytes().collect())));
+        let predicate =
+            Predicate::route_rule("has(queryMap(request.query).param1)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(true));
+
+        property::test::TEST_PROPERTY_VALUE
+            .set(Some(("request.query".into(), "param1=a".bytes().collect())));
+        let predicate =
+            Predicate::route_rule("has(queryMap(request.query).param2)").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(false));
+    }
+
+    #[test]
+    fn connection_id_is_decoded_as_little_endian_uint() {
+        // past `i64::MAX` too, for any sign mixup to show
+        for id in [0x0102_0304_0506_0708_u64, u64::MAX - 1] {
+            property::test::TEST_PROPERTY_VALUE
+                .set(Some(("connection.id".into(), id.to_le_bytes().into())));
+            let value = Expression::new("connection.id")
+                .expect("This must be valid CEL")
+                .eval();
+            assert_eq!(value, Ok(Value::UInt(id)));
+
+            // as a descriptor value, e.g. to limit the requests per connection
+            property::test::TEST_PROPERTY_VALUE
+                .set(Some(("connection.id".into(), id.to_le_bytes().into())));
+            let value = Expression::new("'connection:' + string(connection.id)")
+                .expect("This must be valid CEL")
+                .eval();
+            assert_eq!(value, Ok(format!("connection:{id}").into()));
+        }
+    }
+
+    #[test]
+    fn grpc_attributes_are_null_for_other_requests() {
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            property::Path::new(vec!["request", "headers", "content-type"]),
+            "application/json".bytes().collect(),
+        )));
+        let value = Expression::new("request.grpc.method")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, Value::Null);
+
+        property::test::TEST_PROPERTY_VALUE.set(Some((
+            property::Path::new(vec!["request", "headers", "content-type"]),
+            "application/json".bytes().collect(),
+        )));
+        let predicate =
+            Predicate::new("request.grpc.service == 'Greeter'").expect("This is valid!");
+        assert_eq!(predicate.test(), Ok(false));
+    }
+
     #[test]
     fn decodes_query_string() {
         property::test::TEST_PROPERTY_VALUE.set(Some((
@@ -818,6 +1363,216 @@ mod tests {
         }
     }
 
+    #[test]
+    fn coalesce_headers() {
+        let value = Expression::new("coalesceHeaders('x-api-key', 'x-auth')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, "kuadrant".into());
+
+        let value = Expression::new("coalesceHeaders('x-api-key', 'authorization')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn match_header() {
+        let value = Expression::new("matchHeader('x-auth', '^kuad')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, true.into());
+
+        let value = Expression::new("matchHeader('X-Auth', 'bot|crawler')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, false.into());
+
+        let value = Expression::new("matchHeader('user-agent', '.*')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, false.into());
+
+        assert!(Expression::new("matchHeader('x-auth', '(')")
+            .expect("This is valid CEL!")
+            .eval()
+            .is_err());
+    }
+
+    #[test]
+    fn now() {
+        // 2024-03-01T12:00:00Z
+        let received = 1_709_294_400_000_000_000_i64;
+        property::test::TEST_PROPERTY_VALUE
+            .set(Some(("request.time".into(), received.to_le_bytes().into())));
+        let e = Expression::new("now() == timestamp('2024-03-01T12:00:00Z')")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(true.into()));
+
+        // the request's time is fetched once per evaluation
+        for (expression, expected) in [
+            (
+                "now() - duration('5m') == timestamp('2024-03-01T11:55:00Z')",
+                true,
+            ),
+            (
+                "timestamp('2024-03-01T11:59:00Z') > now() - duration('90s')",
+                true,
+            ),
+            (
+                "timestamp('2024-03-01T11:58:00Z') > now() - duration('1m30s')",
+                false,
+            ),
+            (
+                "now() + duration('1h') == timestamp('2024-03-01T13:00:00Z')",
+                true,
+            ),
+        ] {
+            property::test::TEST_PROPERTY_VALUE
+                .set(Some(("request.time".into(), received.to_le_bytes().into())));
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{expression}");
+        }
+
+        let e = Expression::new("duration('5 minutes')").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some("request.time".into()));
+        let e = Expression::new("now()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn uuid() {
+        let is_v4 = |uuid: &str| {
+            let groups: Vec<&str> = uuid.split('-').collect();
+            groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+                && groups
+                    .iter()
+                    .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+                && groups[2].starts_with('4')
+                && groups[3].starts_with(['8', '9', 'a', 'b'])
+        };
+        let request_id = b"2f3ed6b4-39a5-4a53-b3c9-0c22ab3bcb6f".to_vec();
+
+        property::test::TEST_PROPERTY_VALUE.set(Some(("request.id".into(), request_id.clone())));
+        let e = Expression::new("uuid()").expect("This must be valid CEL");
+        let Ok(Value::String(first)) = e.eval() else {
+            panic!("uuid() must evaluate to a string");
+        };
+        assert!(is_v4(&first), "{first}");
+
+        property::test::TEST_PROPERTY_VALUE.set(Some(("request.id".into(), request_id)));
+        let Ok(Value::String(second)) = e.eval() else {
+            panic!("uuid() must evaluate to a string");
+        };
+        assert!(is_v4(&second), "{second}");
+        assert_ne!(first, second);
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some("request.id".into()));
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn matches() {
+        for (expression, expected) in [
+            ("matches('/v1/toys', '^/v[0-9]+/')", true),
+            ("matches('/api/v1/toys', '^/v[0-9]+/')", false),
+            ("matches('/api/v1/toys', '/v[0-9]+/')", true),
+            ("matches('/toys', '^/toys$')", true),
+            ("matches('/toys/cat', '^/toys$')", false),
+            ("'/admin/toys'.matches('^/admin')", true),
+        ] {
+            let value = Expression::new(expression)
+                .expect("This is valid CEL!")
+                .eval();
+            assert_eq!(value, Ok(expected.into()), "{expression}");
+        }
+
+        property::test::TEST_PROPERTY_VALUE
+            .set(Some(("request.url_path".into(), b"/v2/toys".to_vec())));
+        let predicate = Predicate::new("matches(request.url_path, '^/v[0-9]+/toys')")
+            .expect("This is valid CEL!");
+        assert_eq!(predicate.test(), Ok(true));
+
+        for expression in [
+            "matches('/toys', '(')",
+            "matches(42, '4')",
+            "matches('/toys', 42)",
+            "matches(null, '.*')",
+        ] {
+            assert!(
+                Expression::new(expression)
+                    .expect("This is valid CEL!")
+                    .eval()
+                    .is_err(),
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn size() {
+        for (expression, expected) in [
+            ("size('toys')", 4),
+            ("size('toys ©')", 6),
+            ("'toys ©'.size()", 6),
+            ("size('')", 0),
+            ("size(b'toys')", 4),
+            ("size([1, 2, 3])", 3),
+            ("size({'a': 1, 'b': 2})", 2),
+            ("size(request.headers)", 1),
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{expression}");
+        }
+
+        let predicate = Predicate::new("size(request.headers) > 50").expect("This is valid CEL!");
+        assert_eq!(predicate.test(), Ok(false));
+        let predicate = Predicate::new("request.headers.size() == 1").expect("This is valid CEL!");
+        assert_eq!(predicate.test(), Ok(true));
+
+        for expression in ["size(42)", "size(null)", "size(true)"] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn response_trailer() {
+        let value = Expression::new("responseTrailer('X-Usage-Tokens')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, "42".into());
+
+        let value = Expression::new("responseTrailer('grpc-message')")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, Value::Null);
+
+        let value = Expression::new("response.trailers['grpc-status'] == '0'")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, true.into());
+    }
+
+    #[test]
+    fn response_headers() {
+        let value = Expression::new("response.headers['content-type'] == 'application/json'")
+            .expect("This is valid CEL!")
+            .eval()
+            .expect("This must evaluate!");
+        assert_eq!(value, true.into());
+    }
+
     #[test]
     fn expression_access_host() {
         property::test::TEST_PROPERTY_VALUE.set(Some((
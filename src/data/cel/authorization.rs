@@ -0,0 +1,168 @@
+use crate::data::cel::json_value_to_cel;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use cel_interpreter::extractors::This;
+use cel_interpreter::objects::ValueType;
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+
+/// The scheme of an `Authorization` header value, e.g. `Bearer`, as sent.
+///
+/// An absent header, e.g. from `coalesceHeaders`, evaluates to `null`.
+pub fn auth_scheme(This(header): This<Value>) -> ResolveResult {
+    match header {
+        Value::Null => Ok(Value::Null),
+        Value::String(ref header) => {
+            let (scheme, _) = split_credentials("authScheme", header)?;
+            Ok(scheme.to_string().into())
+        }
+        _ => Err(header.error_expected_type(ValueType::String)),
+    }
+}
+
+/// The credentials of a `Bearer` or `Basic` `Authorization` header value: the token as is for
+/// the former, decoded to `user:pass` for the latter.
+///
+/// An absent header, or one of any other scheme, evaluates to `null`.
+pub fn bearer_token(This(header): This<Value>) -> ResolveResult {
+    let header = match header {
+        Value::Null => return Ok(Value::Null),
+        Value::String(ref header) => header,
+        _ => return Err(header.error_expected_type(ValueType::String)),
+    };
+    let (scheme, credentials) = split_credentials("bearerToken", header)?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Ok(credentials.to_string().into())
+    } else if scheme.eq_ignore_ascii_case("basic") {
+        STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .map(Value::from)
+            .ok_or_else(|| ExecutionError::FunctionError {
+                function: "bearerToken".to_owned(),
+                message: "Malformed Basic credentials".to_owned(),
+            })
+    } else {
+        Ok(Value::Null)
+    }
+}
+
+/// The named claim of a JWT's payload, as its CEL counterpart, or `null` if absent. The
+/// token's signature is NOT verified, which is up to the auth service.
+pub fn jwt_claim(token: Arc<String>, claim: Arc<String>) -> ResolveResult {
+    let malformed = |reason: &str| ExecutionError::FunctionError {
+        function: "jwtClaim".to_owned(),
+        message: format!("Malformed JWT: {reason}"),
+    };
+    let mut parts = token.split('.');
+    let (Some(_header), Some(payload), Some(_signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed("expects 3 `.` separated parts"));
+    };
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|e| malformed(&e.to_string()))?;
+    match serde_json::from_slice(&payload).map_err(|e| malformed(&e.to_string()))? {
+        JsonValue::Object(mut claims) => Ok(claims
+            .remove(claim.as_str())
+            .map(json_value_to_cel)
+            .unwrap_or(Value::Null)),
+        _ => Err(malformed("expects the payload to be a JSON object")),
+    }
+}
+
+fn split_credentials<'a>(
+    function: &str,
+    header: &'a str,
+) -> Result<(&'a str, &'a str), ExecutionError> {
+    header
+        .trim()
+        .split_once(' ')
+        .map(|(scheme, credentials)| (scheme, credentials.trim_start()))
+        .filter(|(scheme, credentials)| !scheme.is_empty() && !credentials.contains(' '))
+        .ok_or_else(|| ExecutionError::FunctionError {
+            function: function.to_owned(),
+            message: "Expects an Authorization header value, i.e. `<scheme> <credentials>`"
+                .to_owned(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Expression;
+    use cel_interpreter::Value;
+    use std::sync::Arc;
+
+    #[test]
+    fn auth_scheme() {
+        let e = Expression::new("'Bearer abc.def'.authScheme()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("Bearer".into()));
+        let e =
+            Expression::new("'Basic dXNlcjpwYXNz'.authScheme()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("Basic".into()));
+        let e = Expression::new("null.authScheme()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(Value::Null));
+
+        let e = Expression::new("'Bearer'.authScheme()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn bearer_token() {
+        let e = Expression::new("'Bearer abc.def'.bearerToken()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("abc.def".into()));
+        let e =
+            Expression::new("'bearer  abc.def '.bearerToken()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("abc.def".into()));
+        let e =
+            Expression::new("'Basic dXNlcjpwYXNz'.bearerToken()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("user:pass".into()));
+        let e = Expression::new("'Digest username=\"user\"'.bearerToken()")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(Value::Null));
+        let e = Expression::new("null.bearerToken()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(Value::Null));
+
+        let e = Expression::new("'Bearer a b'.bearerToken()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("'Basic !!!'.bearerToken()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("''.bearerToken()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn jwt_claim() {
+        // {"alg":"none"} . {"sub":"alice","exp":1700000000,"aud":["toystore"],"ratio":0.5} .
+        let token = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJhbGljZSIsImV4cCI6MTcwMDAwMDAwMCwiYXVkIjpbInRveXN0b3JlIl0sInJhdGlvIjowLjV9.";
+        let claim = |claim: &str| {
+            Expression::new(&format!("jwtClaim('{token}', '{claim}')"))
+                .expect("This must be valid CEL")
+                .eval()
+        };
+        assert_eq!(claim("sub"), Ok("alice".into()));
+        assert_eq!(claim("exp"), Ok(1700000000u64.into()));
+        assert_eq!(claim("ratio"), Ok(0.5.into()));
+        assert_eq!(
+            claim("aud"),
+            Ok(Value::List(Arc::new(vec!["toystore".into()])))
+        );
+        assert_eq!(claim("email"), Ok(Value::Null));
+
+        for token in [
+            "",
+            "not-a-jwt",
+            "eyJhbGciOiJub25lIn0.!!!.",
+            // payload `[1]`
+            "eyJhbGciOiJub25lIn0.WzFd.",
+            "eyJhbGciOiJub25lIn0.e30..",
+        ] {
+            let e = Expression::new(&format!("jwtClaim('{token}', 'sub')"))
+                .expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{token}");
+        }
+    }
+}
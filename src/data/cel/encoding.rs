@@ -0,0 +1,208 @@
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::objects::ValueType;
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+use std::sync::Arc;
+
+/// Formats a hex encoded certificate digest, e.g. `connection.sha256_peer_certificate_digest`,
+/// using one of the common fingerprint styles:
+/// - `colon`: uppercase hex bytes separated by `:`
+/// - `base64`: the standard base64 encoding of the raw digest
+/// - `hex`: lowercase hex, as Envoy provides it
+///
+/// An absent digest evaluates to `null` and an empty one to an empty string.
+pub fn format_fingerprint(This(digest): This<Value>, style: Arc<String>) -> ResolveResult {
+    let digest = match digest {
+        Value::Null => return Ok(Value::Null),
+        Value::String(ref s) => s.trim().to_ascii_lowercase(),
+        _ => return Err(digest.error_expected_type(ValueType::String)),
+    };
+    if digest.is_empty() {
+        return Ok("".into());
+    }
+    let bytes = decode_hex(&digest).ok_or_else(|| ExecutionError::FunctionError {
+        function: "formatFingerprint".to_owned(),
+        message: format!("Expects a hex encoded digest, got `{digest}`"),
+    })?;
+    match style.as_str() {
+        "colon" => Ok(bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(":")
+            .into()),
+        "base64" => Ok(STANDARD.encode(bytes).into()),
+        "hex" => Ok(digest.into()),
+        _ => Err(ExecutionError::FunctionError {
+            function: "formatFingerprint".to_owned(),
+            message: format!("Unknown style `{style}`, expects one of `colon`, `base64` or `hex`"),
+        }),
+    }
+}
+
+// Both alphabets decode with or without padding, the URL-safe one encoding without, as in JWTs
+const BASE64_STANDARD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+const BASE64_URL_SAFE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Base64 encodes a string's UTF-8 bytes, or bytes, e.g. `base64Encode(auth.identity.sub)`,
+/// with the `standard` alphabet unless `url` is given as the second argument.
+pub fn base64_encode(Arguments(args): Arguments) -> ResolveResult {
+    let (value, engine) = base64_arguments("base64Encode", &args)?;
+    match value {
+        Value::String(s) => Ok(engine.encode(s.as_bytes()).into()),
+        Value::Bytes(b) => Ok(engine.encode(b.as_slice()).into()),
+        _ => Err(ExecutionError::FunctionError {
+            function: "base64Encode".to_owned(),
+            message: format!("Expects a string or bytes, got `{value:?}`"),
+        }),
+    }
+}
+
+/// Decodes a base64 string to bytes, e.g. `string(base64Decode(request.headers['x-id'], 'url'))`,
+/// with the `standard` alphabet unless `url` is given as the second argument. Padding is
+/// optional.
+pub fn base64_decode(Arguments(args): Arguments) -> ResolveResult {
+    let (value, engine) = base64_arguments("base64Decode", &args)?;
+    let Value::String(s) = value else {
+        return Err(ExecutionError::FunctionError {
+            function: "base64Decode".to_owned(),
+            message: format!("Expects a string, got `{value:?}`"),
+        });
+    };
+    engine
+        .decode(s.trim())
+        .map(|bytes| Value::Bytes(bytes.into()))
+        .map_err(|err| ExecutionError::FunctionError {
+            function: "base64Decode".to_owned(),
+            message: format!("Invalid base64 `{s}`: {err}"),
+        })
+}
+
+fn base64_arguments<'a>(
+    function: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, &'static GeneralPurpose), ExecutionError> {
+    let error = |message: String| ExecutionError::FunctionError {
+        function: function.to_owned(),
+        message,
+    };
+    match args {
+        [value] => Ok((value, &BASE64_STANDARD)),
+        [value, Value::String(alphabet)] => match alphabet.as_str() {
+            "standard" => Ok((value, &BASE64_STANDARD)),
+            "url" => Ok((value, &BASE64_URL_SAFE)),
+            _ => Err(error(format!(
+                "Unknown alphabet `{alphabet}`, expects `standard` or `url`"
+            ))),
+        },
+        _ => Err(error(format!(
+            "Expects a value and an optional alphabet, got `{args:?}`"
+        ))),
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Expression;
+    use cel_interpreter::Value;
+
+    #[test]
+    fn format_fingerprint() {
+        let e = Expression::new("formatFingerprint('0a1b2c', 'colon')")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("0A:1B:2C".into()));
+        let e = Expression::new("formatFingerprint('0A1B2C', 'base64')")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("Chss".into()));
+        let e =
+            Expression::new("formatFingerprint('0A1B2C', 'hex')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("0a1b2c".into()));
+
+        let e = Expression::new("formatFingerprint('', 'colon')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("".into()));
+        let e =
+            Expression::new("formatFingerprint(null, 'colon')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(Value::Null));
+
+        let e =
+            Expression::new("formatFingerprint('0a1', 'colon')").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("formatFingerprint('0a1b2c', 'dotted')")
+            .expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn base64() {
+        for (expression, expected) in [
+            ("base64Encode('alice')", "YWxpY2U="),
+            ("base64Encode(b'alice')", "YWxpY2U="),
+            ("base64Encode('alice', 'standard')", "YWxpY2U="),
+            ("base64Encode('alice', 'url')", "YWxpY2U"),
+            ("base64Encode('??>', 'standard')", "Pz8+"),
+            ("base64Encode('??>', 'url')", "Pz8-"),
+            ("base64Encode('')", ""),
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{expression}");
+        }
+
+        for (expression, expected) in [
+            ("base64Decode('YWxpY2U=')", "alice"),
+            ("base64Decode('YWxpY2U')", "alice"),
+            ("base64Decode('YWxpY2U', 'url')", "alice"),
+            ("base64Decode('YWxpY2U=', 'url')", "alice"),
+            ("base64Decode('Pz8-', 'url')", "??>"),
+            ("base64Decode('Pz8+')", "??>"),
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(
+                e.eval(),
+                Ok(Value::Bytes(expected.as_bytes().to_vec().into())),
+                "{expression}"
+            );
+        }
+
+        // round-trips
+        let e = Expression::new("string(base64Decode(base64Encode('toys ©', 'url'), 'url'))")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("toys ©".into()));
+        let e =
+            Expression::new("base64Decode(base64Encode(b'toys'))").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(Value::Bytes(b"toys".to_vec().into())));
+
+        for expression in [
+            "base64Decode('not base64!')",
+            "base64Decode('Pz8-')",
+            "base64Decode('Pz8+', 'url')",
+            "base64Decode(b'YWxpY2U=')",
+            "base64Encode(42)",
+            "base64Encode('alice', 'hex')",
+            "base64Encode()",
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use cel_interpreter::extractors::This;
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::Arc;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Maps the string to one of `buckets` buckets, as an index in `0..buckets`. Relies on the
+/// 64 bits FNV-1a hash of its UTF-8 bytes, so that all instances agree on the bucket whatever
+/// their build or architecture.
+pub fn stable_hash(This(key): This<Arc<String>>, buckets: i64) -> ResolveResult {
+    if buckets <= 0 {
+        return Err(ExecutionError::FunctionError {
+            function: "stableHash".to_owned(),
+            message: format!("Expects a positive number of buckets, got `{buckets}`"),
+        });
+    }
+    Ok(((fnv1a(key.as_bytes()) % buckets as u64) as i64).into())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// The SHA-256 digest of a string's UTF-8 bytes, or of bytes, in lowercase hex, e.g. so that
+/// `sha256(auth.identity.sub)` buckets requests by user without the id reaching the service.
+pub fn sha256(This(input): This<Value>) -> ResolveResult {
+    let digest = match &input {
+        Value::String(s) => Sha256::digest(s.as_bytes()),
+        Value::Bytes(b) => Sha256::digest(b.as_slice()),
+        _ => {
+            return Err(ExecutionError::FunctionError {
+                function: "sha256".to_owned(),
+                message: format!("Expects a string or bytes, got `{input:?}`"),
+            })
+        }
+    };
+    Ok(digest
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Expression;
+    use cel_interpreter::Value;
+
+    #[test]
+    fn stable_hash() {
+        assert_eq!(super::fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(super::fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        let e = Expression::new("stableHash('alice', 100)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(83.into()));
+        assert_eq!(e.eval(), Ok(83.into()));
+        let e = Expression::new("stableHash('bob', 100)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(92.into()));
+        let e = Expression::new("stableHash('alice', 1)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(0.into()));
+
+        let e = Expression::new("stableHash('alice', 0)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn stable_hash_spreads_evenly() {
+        let mut buckets = [0; 10];
+        for i in 0..10_000 {
+            let e = Expression::new(&format!("stableHash('user-{i}', 10)"))
+                .expect("This must be valid CEL");
+            match e.eval() {
+                Ok(Value::Int(bucket)) => buckets[bucket as usize] += 1,
+                other => panic!("Unexpected bucket {other:?}"),
+            }
+        }
+        assert!(buckets.iter().all(|count| (900..=1100).contains(count)));
+    }
+
+    #[test]
+    fn sha256() {
+        let e = Expression::new("sha256('')").expect("This must be valid CEL");
+        assert_eq!(
+            e.eval(),
+            Ok("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into())
+        );
+        let e = Expression::new("sha256('abc')").expect("This must be valid CEL");
+        assert_eq!(
+            e.eval(),
+            Ok("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into())
+        );
+        let e = Expression::new("sha256(base64Decode('YWJj'))").expect("This must be valid CEL");
+        assert_eq!(
+            e.eval(),
+            Ok("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into())
+        );
+
+        let e = Expression::new("sha256(1)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::data::get_attribute;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::Key;
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+use std::net::{IpAddr, SocketAddr};
+
+/// Whether the address is within the CIDR block, e.g. `inCidr(source.address, '10.0.0.0/8')`.
+/// The address may carry a port, as `source.address` does, and IPv4-mapped IPv6 addresses are
+/// compared as the IPv4 address they map.
+///
+/// Anything that isn't an address, nor a block, of the same family is merely not within, so
+/// that a predicate doesn't fail on, e.g., an absent attribute.
+pub fn in_cidr(Arguments(args): Arguments) -> ResolveResult {
+    let [address, cidr] = args.as_slice() else {
+        return Err(ExecutionError::FunctionError {
+            function: "inCidr".to_owned(),
+            message: format!("Expects 2 arguments, got `{args:?}`"),
+        });
+    };
+    let (Value::String(address), Value::String(cidr)) = (address, cidr) else {
+        return Ok(false.into());
+    };
+    Ok(parse_address(address)
+        .zip(parse_cidr(cidr))
+        .is_some_and(|(address, (network, prefix))| within(address, network, prefix))
+        .into())
+}
+
+// An address, with or without a port, or between brackets for IPv6 ones
+fn parse_address(address: &str) -> Option<IpAddr> {
+    let address = address
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| address.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            address
+                .strip_prefix('[')
+                .and_then(|address| address.strip_suffix(']'))
+                .and_then(|address| address.parse().ok())
+        })?;
+    Some(match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        v4 => v4,
+    })
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u32)> {
+    let (network, prefix) = cidr.split_once('/')?;
+    let network = network.parse::<IpAddr>().ok()?;
+    let prefix = prefix.parse::<u32>().ok()?;
+    let max = match network {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    (prefix <= max).then_some((network, prefix))
+}
+
+fn within(address: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Whether the destination port is consistent with the scheme of the request, e.g. not `https`
+/// on port 80, to flag anomalous requests. Ports are the standard ones, 80 for `http` and 443
+/// for `https`, and those of the optional map, e.g. `portMatchesScheme({'https': [8443]})`.
+///
+/// Only a port known to be another scheme's contradicts the scheme: any other port, as well as
+/// the requests missing either attribute, match.
+pub fn port_matches_scheme(Arguments(args): Arguments) -> ResolveResult {
+    let mut ports = vec![
+        ("http".to_owned(), vec![80]),
+        ("https".to_owned(), vec![443]),
+    ];
+    match args.as_slice() {
+        [] => {}
+        [Value::Map(extra)] => {
+            for (scheme, extra) in extra.map.iter() {
+                let (Key::String(scheme), Value::List(extra)) = (scheme, extra) else {
+                    return Err(port_matches_scheme_error(format!(
+                        "Expects lists of ports per scheme, got `{scheme:?}: {extra:?}`"
+                    )));
+                };
+                let mut scheme_ports = Vec::with_capacity(extra.len());
+                for port in extra.iter() {
+                    let Value::Int(port) = port else {
+                        return Err(port_matches_scheme_error(format!(
+                            "Expects ports, got `{port:?}`"
+                        )));
+                    };
+                    scheme_ports.push(*port);
+                }
+                let scheme = scheme.to_ascii_lowercase();
+                match ports.iter_mut().find(|(known, _)| *known == scheme) {
+                    Some((_, known)) => known.extend(scheme_ports),
+                    None => ports.push((scheme, scheme_ports)),
+                }
+            }
+        }
+        _ => {
+            return Err(port_matches_scheme_error(format!(
+                "Expects an optional map of ports per scheme, got `{args:?}`"
+            )))
+        }
+    }
+    let Some(scheme) =
+        get_attribute::<String>(&"request.scheme".into()).map_err(port_matches_scheme_error)?
+    else {
+        return Ok(true.into());
+    };
+    let Some(port) =
+        get_attribute::<i64>(&"destination.port".into()).map_err(port_matches_scheme_error)?
+    else {
+        return Ok(true.into());
+    };
+    Ok(port_matches(&scheme, port, &ports).into())
+}
+
+fn port_matches(scheme: &str, port: i64, ports: &[(String, Vec<i64>)]) -> bool {
+    let mut owners = ports
+        .iter()
+        .filter(|(_, ports)| ports.contains(&port))
+        .map(|(owner, _)| owner)
+        .peekable();
+    owners.peek().is_none() || owners.any(|owner| owner.eq_ignore_ascii_case(scheme))
+}
+
+fn port_matches_scheme_error(message: String) -> ExecutionError {
+    ExecutionError::FunctionError {
+        function: "portMatchesScheme".to_owned(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::port_matches;
+    use crate::data::property;
+    use crate::data::Expression;
+
+    fn in_cidr(address: &str, cidr: &str) -> bool {
+        Expression::new(&format!("inCidr('{address}', '{cidr}')"))
+            .expect("This must be valid CEL")
+            .eval()
+            .expect("This must evaluate")
+            == true.into()
+    }
+
+    #[test]
+    fn in_cidr_v4() {
+        assert!(in_cidr("10.1.2.3", "10.0.0.0/8"));
+        assert!(in_cidr("10.1.2.3:43210", "10.0.0.0/8"));
+        assert!(in_cidr("192.168.1.1", "192.168.1.1/32"));
+        assert!(in_cidr("8.8.8.8", "0.0.0.0/0"));
+        assert!(!in_cidr("11.1.2.3", "10.0.0.0/8"));
+        assert!(!in_cidr("192.168.1.2", "192.168.1.1/32"));
+    }
+
+    #[test]
+    fn in_cidr_v6() {
+        assert!(in_cidr("2001:db8::1", "2001:db8::/32"));
+        assert!(in_cidr("[2001:db8::1]:443", "2001:db8::/32"));
+        assert!(in_cidr("[::1]", "::1/128"));
+        assert!(in_cidr("fe80::1", "::/0"));
+        assert!(!in_cidr("2001:db9::1", "2001:db8::/32"));
+    }
+
+    #[test]
+    fn in_cidr_mixed_families() {
+        assert!(!in_cidr("10.1.2.3", "::/0"));
+        assert!(!in_cidr("2001:db8::1", "0.0.0.0/0"));
+        // IPv4-mapped
+        assert!(in_cidr("::ffff:10.1.2.3", "10.0.0.0/8"));
+    }
+
+    #[test]
+    fn in_cidr_malformed() {
+        assert!(!in_cidr("", "10.0.0.0/8"));
+        assert!(!in_cidr("not-an-ip", "10.0.0.0/8"));
+        assert!(!in_cidr("10.1.2.3", "10.0.0.0"));
+        assert!(!in_cidr("10.1.2.3", "10.0.0.0/33"));
+        assert!(!in_cidr("10.1.2.3", "10.0.0.0/-1"));
+        assert!(!in_cidr("10.1.2.3", "not-a-cidr/8"));
+
+        let e = Expression::new("inCidr(null, '10.0.0.0/8')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(false.into()));
+        let e = Expression::new("inCidr('10.1.2.3')").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn port_matches_scheme() {
+        let ports = [
+            ("http".to_owned(), vec![80]),
+            ("https".to_owned(), vec![443, 8443]),
+        ];
+        assert!(port_matches("http", 80, &ports));
+        assert!(port_matches("HTTPS", 443, &ports));
+        assert!(port_matches("https", 8443, &ports));
+        // not known to be any scheme's
+        assert!(port_matches("http", 8080, &ports));
+
+        assert!(!port_matches("https", 80, &ports));
+        assert!(!port_matches("http", 443, &ports));
+        assert!(!port_matches("http", 8443, &ports));
+    }
+
+    #[test]
+    fn port_matches_scheme_without_scheme() {
+        property::test::TEST_ABSENT_PROPERTY.set(Some("request.scheme".into()));
+        let e = Expression::new("portMatchesScheme()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(true.into()));
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some("request.scheme".into()));
+        let e = Expression::new("portMatchesScheme({'https': [8443]})")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(true.into()));
+
+        let e = Expression::new("portMatchesScheme({'https': ['8443']})")
+            .expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("portMatchesScheme(8443)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Constrains a number to the range `[min, max]`. Integers stay integers, i.e. the value or the
+/// bound it's constrained to, while any float among the arguments makes the result a float,
+/// e.g. `clamp(12, 0, 10.5)` is `10.5`.
+pub fn clamp(Arguments(args): Arguments) -> ResolveResult {
+    let [value, min, max] = args.as_slice() else {
+        return Err(ExecutionError::FunctionError {
+            function: "clamp".to_owned(),
+            message: format!("Expects 3 arguments, got `{args:?}`"),
+        });
+    };
+    if args.iter().any(|arg| matches!(arg, Value::Float(_))) {
+        let (value, min, max) = (
+            as_float("clamp", value)?,
+            as_float("clamp", min)?,
+            as_float("clamp", max)?,
+        );
+        check_range(min <= max, &args)?;
+        Ok(value.max(min).min(max).into())
+    } else {
+        let (int, int_min, int_max) = (
+            as_int("clamp", value)?,
+            as_int("clamp", min)?,
+            as_int("clamp", max)?,
+        );
+        check_range(int_min <= int_max, &args)?;
+        Ok(if int < int_min {
+            min.clone()
+        } else if int > int_max {
+            max.clone()
+        } else {
+            value.clone()
+        })
+    }
+}
+
+/// The smallest of 2 or more numbers, e.g. `min(ratelimit.hits_addend, 100)`. As with
+/// `clamp`, integers stay integers, i.e. the argument picked, whether signed or not, while any
+/// float among the arguments makes the result a float.
+pub fn min(Arguments(args): Arguments) -> ResolveResult {
+    extremum("min", &args, Ordering::Less)
+}
+
+/// The largest of 2 or more numbers, e.g. `max(ratelimit.hits_addend, 1)`, see `min`.
+pub fn max(Arguments(args): Arguments) -> ResolveResult {
+    extremum("max", &args, Ordering::Greater)
+}
+
+/// The absolute value of a number, of the same type.
+pub fn abs(This(value): This<Value>) -> ResolveResult {
+    match value {
+        Value::Int(i) => {
+            i.checked_abs()
+                .map(Value::Int)
+                .ok_or_else(|| ExecutionError::FunctionError {
+                    function: "abs".to_owned(),
+                    message: format!("Overflows for `{i}`"),
+                })
+        }
+        Value::UInt(_) => Ok(value),
+        Value::Float(f) => Ok(f.abs().into()),
+        _ => Err(not_a_number("abs", &value)),
+    }
+}
+
+/// Parses the string as an integer, in base 10 unless given another one from 2 to 36, e.g.
+/// `parseInt(request.headers['x-quota']) > 100`. Surrounding whitespace is ignored, as is a
+/// `0x` prefix in base 16, e.g. `parseInt('0x1f', 16)` is `31`.
+pub fn parse_int(Arguments(args): Arguments) -> ResolveResult {
+    let (s, base) = match args.as_slice() {
+        [Value::String(s)] => (s, 10),
+        [Value::String(s), Value::Int(base)] => (s, *base),
+        _ => {
+            return Err(ExecutionError::FunctionError {
+                function: "parseInt".to_owned(),
+                message: format!("Expects a string and an optional base, got `{args:?}`"),
+            })
+        }
+    };
+    if !(2..=36).contains(&base) {
+        return Err(ExecutionError::FunctionError {
+            function: "parseInt".to_owned(),
+            message: format!("Expects a base from 2 to 36, got `{base}`"),
+        });
+    }
+    let trimmed = s.trim();
+    let (sign, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let digits = match base {
+        16 => digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+            .unwrap_or(digits),
+        _ => digits,
+    };
+    let parsed = if digits.starts_with(['+', '-']) {
+        None
+    } else {
+        i64::from_str_radix(&format!("{sign}{digits}"), base as u32).ok()
+    };
+    parsed
+        .map(Value::Int)
+        .ok_or_else(|| ExecutionError::FunctionError {
+            function: "parseInt".to_owned(),
+            message: format!("`{s}` isn't an integer in base {base}"),
+        })
+}
+
+/// Parses the string as a float, e.g. `parseFloat(request.headers['x-weight'])`, surrounding
+/// whitespace ignored.
+pub fn parse_float(s: Arc<String>) -> ResolveResult {
+    s.trim()
+        .parse::<f64>()
+        .map(Value::Float)
+        .map_err(|_| ExecutionError::FunctionError {
+            function: "parseFloat".to_owned(),
+            message: format!("`{s}` isn't a float"),
+        })
+}
+
+// The argument that compares as `wanted` to all others, the first one among equals
+fn extremum(function: &str, args: &[Value], wanted: Ordering) -> ResolveResult {
+    let [first, rest @ ..] = args else {
+        return Err(at_least_2_arguments(function, args));
+    };
+    if rest.is_empty() {
+        return Err(at_least_2_arguments(function, args));
+    }
+    if args.iter().any(|arg| matches!(arg, Value::Float(_))) {
+        let mut extremum = as_float(function, first)?;
+        for arg in rest {
+            let candidate = as_float(function, arg)?;
+            if candidate.partial_cmp(&extremum) == Some(wanted) {
+                extremum = candidate;
+            }
+        }
+        Ok(extremum.into())
+    } else {
+        let (mut extremum, mut extremum_int) = (first, as_int(function, first)?);
+        for arg in rest {
+            let candidate = as_int(function, arg)?;
+            if candidate.cmp(&extremum_int) == wanted {
+                (extremum, extremum_int) = (arg, candidate);
+            }
+        }
+        Ok(extremum.clone())
+    }
+}
+
+fn at_least_2_arguments(function: &str, args: &[Value]) -> ExecutionError {
+    ExecutionError::FunctionError {
+        function: function.to_owned(),
+        message: format!("Expects at least 2 arguments, got `{args:?}`"),
+    }
+}
+
+fn check_range(ordered: bool, args: &[Value]) -> Result<(), ExecutionError> {
+    if ordered {
+        Ok(())
+    } else {
+        Err(ExecutionError::FunctionError {
+            function: "clamp".to_owned(),
+            message: format!("Expects min to be at most max, got `{args:?}`"),
+        })
+    }
+}
+
+fn as_float(function: &str, value: &Value) -> Result<f64, ExecutionError> {
+    match value {
+        Value::Int(i) => Ok(*i as f64),
+        Value::UInt(u) => Ok(*u as f64),
+        Value::Float(f) => Ok(*f),
+        _ => Err(not_a_number(function, value)),
+    }
+}
+
+fn as_int(function: &str, value: &Value) -> Result<i128, ExecutionError> {
+    match value {
+        Value::Int(i) => Ok(i128::from(*i)),
+        Value::UInt(u) => Ok(i128::from(*u)),
+        _ => Err(not_a_number(function, value)),
+    }
+}
+
+fn not_a_number(function: &str, value: &Value) -> ExecutionError {
+    ExecutionError::FunctionError {
+        function: function.to_owned(),
+        message: format!("Expects numbers, got `{value:?}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::property::{test::TEST_PROPERTY_VALUE, Path};
+    use crate::data::Expression;
+
+    #[test]
+    fn clamp() {
+        let e = Expression::new("clamp(-5, 0, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(0.into()));
+        let e = Expression::new("clamp(5, 0, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(5.into()));
+        let e = Expression::new("clamp(15, 0, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(10.into()));
+
+        let e = Expression::new("clamp(15u, 0, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(10.into()));
+        let e = Expression::new("clamp(0.25, 0, 1)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(0.25.into()));
+        let e = Expression::new("clamp(12, 0, 10.5)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(10.5.into()));
+        let e = Expression::new("clamp(-1.5, 0, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(0.0.into()));
+
+        let e = Expression::new("clamp(5, 10, 0)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("clamp('5', 0, 10)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("clamp(5, 0)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn min_max() {
+        let e = Expression::new("min(3, -2, 7)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok((-2).into()));
+        let e = Expression::new("max(3, -2, 7)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(7.into()));
+        let e = Expression::new("max(1u, -2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(1u64.into()));
+        let e = Expression::new("min(1u, -2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok((-2).into()));
+        let e = Expression::new("max(1, 0.5)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(1.0.into()));
+        let e = Expression::new("min(1, 0.5, 2u)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(0.5.into()));
+
+        for expression in ["max(1)", "min()", "max(1, '2')", "min(null, 1)"] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn abs() {
+        let e = Expression::new("(-5).abs()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(5.into()));
+        let e = Expression::new("(5u).abs()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(5u64.into()));
+        let e = Expression::new("(-2.5).abs()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(2.5.into()));
+
+        let e = Expression::new("'5'.abs()").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn parse_int() {
+        for (expression, expected) in [
+            ("parseInt('42')", 42),
+            ("parseInt(' 42 ')", 42),
+            ("parseInt('-42')", -42),
+            ("parseInt('+42')", 42),
+            ("parseInt('42', 10)", 42),
+            ("parseInt('1f', 16)", 31),
+            ("parseInt('0x1f', 16)", 31),
+            ("parseInt('0X1F', 16)", 31),
+            ("parseInt('-0x1f', 16)", -31),
+            ("parseInt('101', 2)", 5),
+            ("parseInt('z', 36)", 35),
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{expression}");
+        }
+
+        for expression in [
+            "parseInt('')",
+            "parseInt('4 2')",
+            "parseInt('0x1f')",
+            "parseInt('1f')",
+            "parseInt('4.2')",
+            "parseInt('-+42')",
+            "parseInt('0x-1f', 16)",
+            "parseInt('2', 2)",
+            "parseInt('9223372036854775808')",
+            "parseInt('42', 1)",
+            "parseInt('42', 37)",
+            "parseInt(42)",
+            "parseInt()",
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+
+        TEST_PROPERTY_VALUE.set(Some((Path::new(vec!["request", "query"]), b"101".to_vec())));
+        let e = Expression::new("parseInt(request.query) > 100").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(true.into()));
+    }
+
+    #[test]
+    fn parse_float() {
+        let e = Expression::new("parseFloat('2.5')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(2.5.into()));
+        let e = Expression::new("parseFloat(' -0.25 ')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok((-0.25).into()));
+        let e = Expression::new("parseFloat('3')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(3.0.into()));
+
+        for expression in ["parseFloat('')", "parseFloat('2.5.1')", "parseFloat('one')"] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn hits_addend_is_at_least_1() {
+        for (hits_addend, expected) in [(0i64, 1i64), (-3, 1), (42, 42)] {
+            TEST_PROPERTY_VALUE.set(Some((
+                Path::new(vec!["ratelimit", "hits_addend"]),
+                hits_addend.to_le_bytes().to_vec(),
+            )));
+            let e =
+                Expression::new("max(ratelimit.hits_addend, 1)").expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{hits_addend}");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::data::cel::strings::split_port;
+use crate::data::get_attribute;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::ValueType;
+use cel_interpreter::{ExecutionError, ResolveResult, Value};
+
+/// Whether the `Host` of the request is the server name the client asked for during the TLS
+/// handshake, which differ for domain fronted requests. A wildcard server name, e.g.
+/// `*.example.com`, matches the hosts of exactly one more label.
+///
+/// Requests without a server name, e.g. not over TLS, evaluate to the optional argument,
+/// `true` by default.
+pub fn host_matches_sni(Arguments(args): Arguments) -> ResolveResult {
+    let without_sni = match args.first() {
+        None => true,
+        Some(Value::Bool(without_sni)) => *without_sni,
+        Some(other) => return Err(other.error_expected_type(ValueType::Bool)),
+    };
+    let sni = get_attribute::<String>(&"connection.requested_server_name".into())
+        .map_err(|e| function_error(&e))?;
+    let Some(sni) = sni.filter(|sni| !sni.is_empty()) else {
+        return Ok(without_sni.into());
+    };
+    let host = get_attribute::<String>(&"request.host".into())
+        .map_err(|e| function_error(&e))?
+        .unwrap_or_default();
+    Ok(host_matches_server_name(&host, &sni).into())
+}
+
+fn host_matches_server_name(host: &str, server_name: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let (host, _) = split_port(&host);
+    let host = host.trim_end_matches('.');
+    let server_name = server_name.to_ascii_lowercase();
+    let server_name = server_name.trim_end_matches('.');
+    match server_name.strip_prefix("*.") {
+        Some(domain) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+        None => host == server_name,
+    }
+}
+
+fn function_error(message: &str) -> ExecutionError {
+    ExecutionError::FunctionError {
+        function: "hostMatchesSni".to_owned(),
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::host_matches_server_name;
+    use crate::data::property;
+    use crate::data::Expression;
+
+    #[test]
+    fn host_matches_server_name_minding_wildcards() {
+        assert!(host_matches_server_name("example.com", "example.com"));
+        assert!(host_matches_server_name("Example.com:443", "example.COM."));
+        assert!(host_matches_server_name("api.example.com", "*.example.com"));
+
+        assert!(!host_matches_server_name("fronted.com", "example.com"));
+        assert!(!host_matches_server_name("example.com.evil", "example.com"));
+        assert!(!host_matches_server_name("example.com", "*.example.com"));
+        assert!(!host_matches_server_name(
+            "a.b.example.com",
+            "*.example.com"
+        ));
+    }
+
+    #[test]
+    fn requests_without_sni_match_as_configured() {
+        property::test::TEST_ABSENT_PROPERTY.set(Some("connection.requested_server_name".into()));
+        let e = Expression::new("hostMatchesSni()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(true.into()));
+
+        property::test::TEST_ABSENT_PROPERTY.set(Some("connection.requested_server_name".into()));
+        let e = Expression::new("hostMatchesSni(false)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(false.into()));
+
+        let e = Expression::new("hostMatchesSni('yes')").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+}
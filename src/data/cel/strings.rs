@@ -2,8 +2,11 @@ use cel_interpreter::extractors::{Arguments, This};
 use cel_interpreter::{ExecutionError, ResolveResult, Value};
 use std::sync::Arc;
 
+/// The character at the index, negative ones counting back from the end, e.g. `charAt(-1)` is
+/// the last one.
 pub fn char_at(This(this): This<Arc<String>>, arg: i64) -> ResolveResult {
-    match this.chars().nth(arg as usize) {
+    let idx = resolve_index("String.charAt", &this, arg, this.chars().count())?;
+    match this.chars().nth(idx) {
         None => Err(ExecutionError::FunctionError {
             function: "String.charAt".to_owned(),
             message: format!("No index {arg} on `{this}`"),
@@ -24,7 +27,7 @@ pub fn index_of(
         },
         2 => {
             let base = match args[1] {
-                Value::Int(i) => i as usize,
+                Value::Int(i) => resolve_index("String.indexOf", &this, i, this.len())?,
                 Value::UInt(u) => u as usize,
                 _ => {
                     return Err(ExecutionError::FunctionError {
@@ -115,6 +118,130 @@ pub fn join(This(this): This<Arc<Vec<Value>>>, Arguments(args): Arguments) -> Re
         .into())
 }
 
+/// Joins the list's values with the separator, e.g. `strConcat(request.url_path.split('/'), '.')`.
+/// Unlike `List.join`, numbers and booleans are stringified, while any other value, including
+/// `null`, is an error.
+pub fn str_concat(list: Arc<Vec<Value>>, separator: Arc<String>) -> ResolveResult {
+    Ok(list
+        .iter()
+        .map(|v| {
+            scalar_to_string(v).ok_or_else(|| ExecutionError::FunctionError {
+                function: "strConcat".to_owned(),
+                message: format!("Expects a list of scalar values, got `{v:?}`"),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .join(separator.as_str())
+        .into())
+}
+
+/// Whether the string starts with any of the prefixes, e.g.
+/// `startsWithAny(request.url_path, ['/admin', '/internal'])`
+pub fn starts_with_any(s: Arc<String>, prefixes: Arc<Vec<Value>>) -> ResolveResult {
+    any_of("startsWithAny", &prefixes, |prefix| s.starts_with(prefix))
+}
+
+/// Whether the string ends with any of the suffixes, e.g.
+/// `endsWithAny(request.url_path, ['.png', '.jpg'])`
+pub fn ends_with_any(s: Arc<String>, suffixes: Arc<Vec<Value>>) -> ResolveResult {
+    any_of("endsWithAny", &suffixes, |suffix| s.ends_with(suffix))
+}
+
+// Whether any of the strings matches, all of them being checked to be strings
+fn any_of(function: &str, list: &[Value], matches: impl Fn(&str) -> bool) -> ResolveResult {
+    let mut found = false;
+    for value in list {
+        match value {
+            Value::String(s) => found = found || matches(s),
+            _ => {
+                return Err(ExecutionError::FunctionError {
+                    function: function.to_owned(),
+                    message: format!("Expects a list of strings, got `{value:?}`"),
+                })
+            }
+        }
+    }
+    Ok(found.into())
+}
+
+/// Fills the template's placeholders with the arguments, in order, e.g.
+/// `format('tenant:%s:route:%s', auth.identity.tenant, request.url_path)`. `%s` takes any
+/// scalar, `%d` an integer and `%f` a number, printed with 6 decimals, while `%%` is a
+/// literal `%`. There must be exactly as many arguments as placeholders.
+pub fn format(Arguments(args): Arguments) -> ResolveResult {
+    let error = |message: String| ExecutionError::FunctionError {
+        function: "format".to_owned(),
+        message,
+    };
+    let [Value::String(template), values @ ..] = args.as_slice() else {
+        return Err(error(format!(
+            "Expects a template and its arguments, got `{args:?}`"
+        )));
+    };
+    let mut values = values.iter();
+    let mut formatted = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        let placeholder = chars.next();
+        if placeholder == Some('%') {
+            formatted.push('%');
+            continue;
+        }
+        let Some(value) = values.next() else {
+            return Err(error(format!(
+                "Expects as many arguments as placeholders in `{template}`, got {}",
+                args.len() - 1
+            )));
+        };
+        let arg = match (placeholder, value) {
+            (Some('s'), value) => scalar_to_string(value),
+            (Some('d'), Value::Int(i)) => Some(i.to_string()),
+            (Some('d'), Value::UInt(u)) => Some(u.to_string()),
+            (Some('f'), Value::Int(i)) => Some(format!("{:.6}", *i as f64)),
+            (Some('f'), Value::UInt(u)) => Some(format!("{:.6}", *u as f64)),
+            (Some('f'), Value::Float(f)) => Some(format!("{f:.6}")),
+            (Some('s' | 'd' | 'f'), _) => None,
+            (placeholder, _) => {
+                return Err(error(format!(
+                    "Unsupported placeholder `%{}` in `{template}`",
+                    placeholder.map(String::from).unwrap_or_default()
+                )))
+            }
+        };
+        match arg {
+            Some(arg) => formatted.push_str(&arg),
+            None => {
+                return Err(error(format!(
+                    "Unexpected `{value:?}` for `%{}`",
+                    placeholder.unwrap_or_default()
+                )))
+            }
+        }
+    }
+    if values.next().is_some() {
+        return Err(error(format!(
+            "Expects as many arguments as placeholders in `{template}`, got {}",
+            args.len() - 1
+        )));
+    }
+    Ok(formatted.into())
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Int(i) => Some(i.to_string()),
+        Value::UInt(u) => Some(u.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
 pub fn lower_ascii(This(this): This<Arc<String>>) -> ResolveResult {
     Ok(this.to_ascii_lowercase().into())
 }
@@ -218,11 +345,14 @@ pub fn split(This(this): This<Arc<String>>, Arguments(args): Arguments) -> Resol
     }
 }
 
+/// The characters from the start index up to the end one, if any, negative ones counting back
+/// from the end, e.g. `substring(-3)` are the last 3.
 pub fn substring(This(this): This<Arc<String>>, Arguments(args): Arguments) -> ResolveResult {
+    let chars = this.chars().count();
     match args.len() {
         count @ 1..=2 => {
             let start = match &args[0] {
-                Value::Int(i) => *i as usize,
+                Value::Int(i) => resolve_index("String.substring", &this, *i, chars)?,
                 Value::UInt(u) => *u as usize,
                 _ => Err(ExecutionError::FunctionError {
                     function: "String.substring".to_string(),
@@ -234,7 +364,7 @@ pub fn substring(This(this): This<Arc<String>>, Arguments(args): Arguments) -> R
             };
             let end = if count == 2 {
                 match &args[1] {
-                    Value::Int(i) => *i as usize,
+                    Value::Int(i) => resolve_index("String.substring", &this, *i, chars)?,
                     Value::UInt(u) => *u as usize,
                     _ => Err(ExecutionError::FunctionError {
                         function: "String.substring".to_string(),
@@ -245,7 +375,7 @@ pub fn substring(This(this): This<Arc<String>>, Arguments(args): Arguments) -> R
                     })?,
                 }
             } else {
-                this.chars().count()
+                chars
             };
             if end < start {
                 Err(ExecutionError::FunctionError {
@@ -267,9 +397,114 @@ pub fn substring(This(this): This<Arc<String>>, Arguments(args): Arguments) -> R
     }
 }
 
+// Python-style index into something `len` long, i.e. negative ones count back from the end
+fn resolve_index(
+    function: &str,
+    this: &str,
+    idx: i64,
+    len: usize,
+) -> Result<usize, ExecutionError> {
+    if idx >= 0 {
+        return Ok(idx as usize);
+    }
+    usize::try_from(idx.unsigned_abs())
+        .ok()
+        .and_then(|back| len.checked_sub(back))
+        .ok_or_else(|| ExecutionError::FunctionError {
+            function: function.to_owned(),
+            message: format!("No index {idx} on `{this}`"),
+        })
+}
+
+pub fn substring_bytes(This(this): This<Arc<String>>, start: i64, len: i64) -> ResolveResult {
+    if start < 0 || len < 0 {
+        return Err(ExecutionError::FunctionError {
+            function: "String.substringBytes".to_owned(),
+            message: format!("Expects non-negative arguments, got `{start}` and `{len}`"),
+        });
+    }
+    // never split a codepoint: skip a partial one at the start, drop a partial one at the end
+    let mut from = (start as usize).min(this.len());
+    let mut to = from.saturating_add(len as usize).min(this.len());
+    while !this.is_char_boundary(from) {
+        from += 1;
+    }
+    while to > from && !this.is_char_boundary(to) {
+        to -= 1;
+    }
+    Ok(this[from..to.max(from)].to_string().into())
+}
+
+/// A descriptor value safe to send to the rate limiting service: control characters, e.g.
+/// newlines, are escaped, as are backslashes so that escaped values stay distinct.
+pub fn escape_descriptor(This(this): This<Arc<String>>) -> ResolveResult {
+    let mut escaped = String::with_capacity(this.len());
+    for c in this.chars() {
+        if c == '\\' || c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Ok(escaped.into())
+}
+
+/// The URL, or path, without its query string, e.g. `withoutQuery(request.url_path)`. Only the
+/// first `?` starts the query, and a fragment, if any, is kept.
+pub fn without_query(url: Arc<String>) -> ResolveResult {
+    let (rest, _, fragment) = split_query(&url);
+    Ok(format!("{rest}{fragment}").into())
+}
+
+/// The query string of the URL, or path, without the leading `?`, empty when there's none
+pub fn query_of(url: Arc<String>) -> ResolveResult {
+    let (_, query, _) = split_query(&url);
+    Ok(query.to_string().into())
+}
+
+// (before the query, the query, the fragment with its `#`)
+fn split_query(url: &str) -> (&str, &str, &str) {
+    let (url, fragment) = match url.find('#') {
+        Some(idx) => url.split_at(idx),
+        None => (url, ""),
+    };
+    match url.split_once('?') {
+        Some((rest, query)) => (rest, query, fragment),
+        None => (url, "", fragment),
+    }
+}
+
+/// Canonical form of a host, so that its variants don't fragment counters: lowercased,
+/// without trailing dots nor a default port.
+pub fn canonical_host(This(this): This<Arc<String>>) -> ResolveResult {
+    let host = this.to_ascii_lowercase();
+    let (name, port) = split_port(&host);
+    let name = name.trim_end_matches('.');
+    Ok(match port {
+        None | Some("80") | Some("443") => name.to_string(),
+        Some(port) => format!("{name}:{port}"),
+    }
+    .into())
+}
+
+// Splits `host[:port]`, minding the colons of IPv6 addresses
+pub(super) fn split_port(host: &str) -> (&str, Option<&str>) {
+    match host.rsplit_once(':') {
+        Some((name, port))
+            if !port.is_empty()
+                && port.bytes().all(|b| b.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            (name, Some(port))
+        }
+        _ => (host, None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::data::Expression;
+    use crate::data::property::{test::TEST_PROPERTY_VALUE, Path};
+    use crate::data::{Expression, Predicate};
     use cel_interpreter::Value;
 
     #[test]
@@ -363,4 +598,258 @@ mod tests {
         let e = Expression::new("'ta©o©αT'.substring(2, 6)").expect("This must be valid CEL");
         assert_eq!(e.eval(), Ok("©o©α".into()));
     }
+
+    #[test]
+    fn negative_indices() {
+        let e = Expression::new("'abc'.charAt(-1)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("c".into()));
+        let e = Expression::new("'abc'.charAt(-3)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("a".into()));
+        let e = Expression::new("'ta©o'.charAt(-2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("©".into()));
+
+        let e = Expression::new("'tacocat'.substring(-3)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("cat".into()));
+        let e = Expression::new("'tacocat'.substring(0, -3)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("taco".into()));
+        let e = Expression::new("'ta©o©αT'.substring(-5, -1)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("©o©α".into()));
+
+        let e = Expression::new("'tacocat'.indexOf('a', -3)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(5.into()));
+        let e = Expression::new("'tacocat'.indexOf('t', -1)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(6.into()));
+
+        for expression in [
+            "'abc'.charAt(-4)",
+            "'tacocat'.substring(-8)",
+            "'tacocat'.substring(0, -8)",
+            "'tacocat'.substring(-1, -3)",
+            "'tacocat'.indexOf('a', -8)",
+            "''.charAt(-1)",
+            "''.substring(-1)",
+            "''.indexOf('', -1)",
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+
+        let e = Expression::new("''.substring(0)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("".into()));
+    }
+
+    #[test]
+    fn substring_bytes() {
+        let e = Expression::new("'tacocat'.substringBytes(0, 4)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("taco".into()));
+        let e = Expression::new("'tacocat'.substringBytes(4, 10)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("cat".into()));
+        let e = Expression::new("'tacocat'.substringBytes(10, 2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("".into()));
+
+        // '©' and 'α' are 2 bytes long, '€' is 3
+        let e = Expression::new("'ta©o'.substringBytes(0, 3)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("ta".into()));
+        let e = Expression::new("'ta©o'.substringBytes(0, 4)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("ta©".into()));
+        let e = Expression::new("'ta©o'.substringBytes(3, 2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("o".into()));
+        let e = Expression::new("'€αT'.substringBytes(0, 2)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("".into()));
+        let e = Expression::new("'€αT'.substringBytes(0, 4)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("€".into()));
+        let e = Expression::new("'€αT'.substringBytes(1, 4)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("α".into()));
+
+        let e = Expression::new("'tacocat'.substringBytes(-1, 2)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn str_concat() {
+        let e = Expression::new("strConcat('/api/v1/toys'.split('/'), '.')")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(".api.v1.toys".into()));
+        let e = Expression::new("strConcat(['toys', 42, 1u, true], ':')")
+            .expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("toys:42:1:true".into()));
+        let e = Expression::new("strConcat([], '/')").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("".into()));
+
+        let e = Expression::new("strConcat(['toys', null], '/')").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+        let e = Expression::new("strConcat(['toys', ['nested']], '/')")
+            .expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn starts_with_any() {
+        for (path, expected) in [
+            ("/admin/toys", true),
+            ("/internal", true),
+            ("/toys/admin", false),
+            ("", false),
+        ] {
+            TEST_PROPERTY_VALUE.set(Some((
+                Path::new(vec!["request", "url_path"]),
+                path.as_bytes().to_vec(),
+            )));
+            let predicate =
+                Predicate::new("startsWithAny(request.url_path, ['/admin', '/internal'])")
+                    .expect("This must be valid CEL");
+            assert_eq!(predicate.test(), Ok(expected), "{path}");
+        }
+
+        let e = Expression::new("startsWithAny('/admin', [])").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok(false.into()));
+        let e = Expression::new("startsWithAny('/admin', ['/admin', 1])")
+            .expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn ends_with_any() {
+        for (path, expected) in [
+            ("/toys/cat.png", true),
+            ("/toys/cat.jpg", true),
+            ("/toys/cat.png/legs", false),
+        ] {
+            TEST_PROPERTY_VALUE.set(Some((
+                Path::new(vec!["request", "url_path"]),
+                path.as_bytes().to_vec(),
+            )));
+            let predicate = Predicate::new("endsWithAny(request.url_path, ['.png', '.jpg'])")
+                .expect("This must be valid CEL");
+            assert_eq!(predicate.test(), Ok(expected), "{path}");
+        }
+
+        let e = Expression::new("endsWithAny('/cat.png', [null])").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn format() {
+        for (expression, expected) in [
+            (
+                "format('tenant:%s:route:%s', 'acme', '/toys')",
+                "tenant:acme:route:/toys",
+            ),
+            ("format('%d hits', 42)", "42 hits"),
+            ("format('%d', 42u)", "42"),
+            ("format('%f', 2.5)", "2.500000"),
+            ("format('%f', 2)", "2.000000"),
+            ("format('%s/%s/%s', true, 1, 1.5)", "true/1/1.5"),
+            ("format('100%%')", "100%"),
+            ("format('no placeholder')", "no placeholder"),
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{expression}");
+        }
+
+        for expression in [
+            "format('%s:%s', 'acme')",
+            "format('%s', 'acme', 'toys')",
+            "format('%d', '42')",
+            "format('%d', 4.2)",
+            "format('%f', 'pi')",
+            "format('%s', null)",
+            "format('%s', ['acme'])",
+            "format('%x', 42)",
+            "format('trailing %', 42)",
+            "format(42)",
+            "format()",
+        ] {
+            let e = Expression::new(expression).expect("This must be valid CEL");
+            assert!(e.eval().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn escape_descriptor() {
+        TEST_PROPERTY_VALUE.set(Some((
+            Path::new(vec!["request", "path"]),
+            b"/toys\ncat\tlegs\0\x7f".to_vec(),
+        )));
+        let e = Expression::new("request.path.escapeDescriptor()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("/toys\\ncat\\tlegs\\u{0}\\u{7f}".into()));
+
+        TEST_PROPERTY_VALUE.set(Some((
+            Path::new(vec!["request", "path"]),
+            b"/toys\\n".to_vec(),
+        )));
+        let e = Expression::new("request.path.escapeDescriptor()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("/toys\\\\n".into()));
+
+        let e = Expression::new("'toys ©'.escapeDescriptor()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("toys ©".into()));
+    }
+
+    #[test]
+    fn without_query() {
+        for (url, expected) in [
+            ("/toys?cat=1", "/toys"),
+            ("/toys", "/toys"),
+            ("/toys?", "/toys"),
+            ("/toys?cat=1?dog=2", "/toys"),
+            ("/toys?cat=1#legs", "/toys#legs"),
+            ("https://example.com/toys?cat=1", "https://example.com/toys"),
+            ("", ""),
+        ] {
+            let e =
+                Expression::new(&format!("withoutQuery('{url}')")).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{url}");
+        }
+
+        TEST_PROPERTY_VALUE.set(Some((
+            Path::new(vec!["request", "url_path"]),
+            b"/toys?cat=1".to_vec(),
+        )));
+        let e = Expression::new("withoutQuery(request.url_path)").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("/toys".into()));
+    }
+
+    #[test]
+    fn query_of() {
+        for (url, expected) in [
+            ("/toys?cat=1", "cat=1"),
+            ("/toys", ""),
+            ("/toys?", ""),
+            ("/toys?cat=1?dog=2", "cat=1?dog=2"),
+            ("/toys?cat=1#legs", "cat=1"),
+            ("/toys#legs?cat=1", ""),
+            ("https://example.com/toys?cat=1&dog=2", "cat=1&dog=2"),
+        ] {
+            let e = Expression::new(&format!("queryOf('{url}')")).expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok(expected.into()), "{url}");
+        }
+
+        let e = Expression::new("queryOf(1)").expect("This must be valid CEL");
+        assert!(e.eval().is_err());
+    }
+
+    #[test]
+    fn canonical_host() {
+        for host in [
+            "example.com",
+            "EXAMPLE.com",
+            "example.com.",
+            "Example.COM.",
+            "example.com:80",
+            "example.com:443",
+            "example.com.:443",
+        ] {
+            let e = Expression::new(&format!("'{host}'.canonicalHost()"))
+                .expect("This must be valid CEL");
+            assert_eq!(e.eval(), Ok("example.com".into()), "{host}");
+        }
+
+        let e =
+            Expression::new("'Example.com.:8080'.canonicalHost()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("example.com:8080".into()));
+        let e = Expression::new("'[::1]:443'.canonicalHost()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("[::1]".into()));
+        let e = Expression::new("'::1'.canonicalHost()").expect("This must be valid CEL");
+        assert_eq!(e.eval(), Ok("::1".into()));
+    }
 }
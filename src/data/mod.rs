@@ -12,4 +12,8 @@ pub use cel::Expression;
 pub use cel::Predicate;
 pub use cel::PredicateVec;
 
+pub use property::install_attribute_settings;
+pub use property::response_body_size;
+pub use property::AttributeSettings;
+pub use property::InstalledAttributeSettings;
 pub use property::Path as PropertyPath;
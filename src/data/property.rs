@@ -2,8 +2,69 @@ use crate::data::attribute::KUADRANT_NAMESPACE;
 use log::debug;
 use log::warn;
 use proxy_wasm::types::Status;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+thread_local!(
+    static ATTRIBUTE_SETTINGS: RefCell<Rc<AttributeSettings>> = RefCell::default();
+    static RESPONSE_BODY_SIZE: Cell<Option<i64>> = const { Cell::new(None) };
+);
+
+/// The settings of a root context's plugin configuration that properties resolve as per. The
+/// root contexts of a VM each have their own, which their filters install for the time of a
+/// callback, see [`install_attribute_settings`].
+#[derive(Debug, Default)]
+pub struct AttributeSettings {
+    /// Whether the `X-Forwarded-Proto` header can be trusted to tell the scheme the client
+    /// used, i.e. it's always set by a proxy in front
+    pub trust_forwarded_proto: bool,
+    /// How many configurations the root context applied so far, i.e. the one in effect
+    pub config_generation: i64,
+    /// Where the proxy in front provides the client's geolocation, e.g. a `x-geo-country`
+    /// header, as the properties `request.geo.country` and `request.geo.asn` resolve to.
+    /// Without one, the corresponding attribute is `null`.
+    pub geo_country_source: Option<Path>,
+    pub geo_asn_source: Option<Path>,
+}
+
+/// The previous settings, back once dropped
+#[must_use]
+pub struct InstalledAttributeSettings(Rc<AttributeSettings>);
+
+impl Drop for InstalledAttributeSettings {
+    fn drop(&mut self) {
+        ATTRIBUTE_SETTINGS.set(Rc::clone(&self.0));
+    }
+}
+
+/// Resolves the properties as per these settings, until the returned guard is dropped
+pub fn install_attribute_settings(settings: Rc<AttributeSettings>) -> InstalledAttributeSettings {
+    InstalledAttributeSettings(ATTRIBUTE_SETTINGS.replace(settings))
+}
+
+fn attribute_settings() -> Rc<AttributeSettings> {
+    ATTRIBUTE_SETTINGS.with_borrow(Rc::clone)
+}
+
+/// The bytes of the response body, once it all went through the filter, as
+/// `response.body_size` resolves to for the request being handled
+pub fn response_body_size(size: Option<i64>) {
+    RESPONSE_BODY_SIZE.set(size);
+}
+
+fn geo(field: &str) -> Result<Option<Vec<u8>>, Status> {
+    let settings = attribute_settings();
+    let source = match field {
+        "country" => &settings.geo_country_source,
+        _ => &settings.geo_asn_source,
+    };
+    match source {
+        None => Ok(None),
+        Some(path) => host_get_property(path),
+    }
+}
 
 fn remote_address() -> Result<Option<Vec<u8>>, Status> {
     // Ref https://www.envoyproxy.io/docs/envoy/latest/configuration/http/http_conn_man/headers#x-forwarded-for
@@ -26,6 +87,170 @@ fn remote_address() -> Result<Option<Vec<u8>>, Status> {
     }
 }
 
+// Correlates the requests multiplexed over a same connection, e.g. HTTP/2 streams
+fn trace_key() -> Result<Option<Vec<u8>>, Status> {
+    let connection_id = host_get_property(&"connection.id".into())?;
+    let request_id = host_get_property(&"request.id".into())?;
+    match (connection_id, request_id) {
+        (Some(connection_id), Some(request_id)) => {
+            let connection_id: [u8; 8] = connection_id.try_into().map_err(|_| {
+                warn!("connection.id property value not an 8 bytes UInt");
+                Status::BadArgument
+            })?;
+            Ok(Some(
+                format_trace_key(
+                    u64::from_le_bytes(connection_id),
+                    &String::from_utf8_lossy(&request_id),
+                )
+                .into_bytes(),
+            ))
+        }
+        _ => Ok(None),
+    }
+}
+
+// `request.grpc.*`, only set for gRPC requests, i.e. to `/{service}/{method}` as `application/grpc`
+fn grpc_call_attribute(field: &str) -> Result<Option<Vec<u8>>, Status> {
+    let content_type = host_get_property(&Path::new(vec!["request", "headers", "content-type"]))?;
+    if !content_type.is_some_and(|content_type| is_grpc_content_type(&content_type)) {
+        return Ok(None);
+    }
+    let url_path = host_get_property(&"request.url_path".into())?;
+    Ok(url_path.and_then(|url_path| {
+        let url_path = String::from_utf8(url_path).ok()?;
+        let (service, method) = grpc_call(&url_path)?;
+        match field {
+            "service" => Some(service.as_bytes().to_vec()),
+            _ => Some(method.as_bytes().to_vec()),
+        }
+    }))
+}
+
+fn is_grpc_content_type(content_type: &[u8]) -> bool {
+    content_type == b"application/grpc"
+        || content_type.starts_with(b"application/grpc+")
+        || content_type.starts_with(b"application/grpc;")
+}
+
+fn grpc_call(url_path: &str) -> Option<(&str, &str)> {
+    let (service, method) = url_path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service, method))
+}
+
+// `request.path` without its query string, percent-decoded, so that an encoded path can't slip past
+// a predicate on its plain form
+fn request_decoded_path() -> Result<Option<Vec<u8>>, Status> {
+    let path = host_get_property(&"request.path".into())?;
+    Ok(path.map(|path| decoded_path(&String::from_utf8_lossy(&path)).into_bytes()))
+}
+
+fn decoded_path(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    match urlencoding::decode(path) {
+        Ok(decoded) => decoded.into_owned(),
+        Err(e) => {
+            warn!("request.path not decodable as UTF-8, left as is: {e}");
+            path.to_string()
+        }
+    }
+}
+
+// The media type of the `Content-Type` header, lowercased and without its parameters, e.g.
+// `application/json` for `application/json; charset=utf-8`, empty when there's none
+fn request_content_type() -> Result<Option<Vec<u8>>, Status> {
+    let header = host_get_property(&Path::new(vec!["request", "headers", "content-type"]))?;
+    Ok(Some(header.map(|h| media_type(&h)).unwrap_or_default()))
+}
+
+fn media_type(header: &[u8]) -> Vec<u8> {
+    header
+        .split(|b| *b == b';')
+        .next()
+        .unwrap_or_default()
+        .trim_ascii()
+        .to_ascii_lowercase()
+}
+
+// What follows the `#` of `request.path`, empty when there's none so that predicates such as
+// `request.fragment.startsWith(...)` hold without checking for it
+fn request_fragment() -> Result<Option<Vec<u8>>, Status> {
+    let path = host_get_property(&"request.path".into())?;
+    Ok(Some(path.map(|path| fragment(&path)).unwrap_or_default()))
+}
+
+fn fragment(path: &[u8]) -> Vec<u8> {
+    path.iter()
+        .position(|b| *b == b'#')
+        .map(|hash| path[hash + 1..].to_vec())
+        .unwrap_or_default()
+}
+
+// The first protocol of the `X-Forwarded-Proto` header, as set by the proxy closest to the client
+fn forwarded_proto() -> Result<Option<Vec<u8>>, Status> {
+    let header = host_get_property(&Path::new(vec!["request", "headers", "x-forwarded-proto"]))?;
+    Ok(header.and_then(|header| first_forwarded_proto(&header)))
+}
+
+fn first_forwarded_proto(header: &[u8]) -> Option<Vec<u8>> {
+    let proto = header.split(|b| *b == b',').next()?.trim_ascii();
+    if proto.is_empty() {
+        return None;
+    }
+    Some(proto.to_ascii_lowercase())
+}
+
+// The scheme the client used, which a TLS terminating proxy in front only tells when trusted
+fn request_scheme() -> Result<Option<Vec<u8>>, Status> {
+    if attribute_settings().trust_forwarded_proto {
+        if let Some(proto) = forwarded_proto()? {
+            return Ok(Some(proto));
+        }
+    }
+    host_get_property(&"request.scheme".into())
+}
+
+fn request_secure() -> Result<Option<Vec<u8>>, Status> {
+    let secure = request_scheme()?.is_some_and(|scheme| scheme.eq_ignore_ascii_case(b"https"));
+    Ok(Some(vec![u8::from(secure)]))
+}
+
+// Whether the request is a WebSocket handshake, the messages exchanged once upgraded never going
+// through the filter
+fn websocket_upgrade() -> Result<Option<Vec<u8>>, Status> {
+    let upgrade = host_get_property(&Path::new(vec!["request", "headers", "upgrade"]))?
+        .is_some_and(|upgrade| has_token(&upgrade, b"websocket"))
+        && host_get_property(&Path::new(vec!["request", "headers", "connection"]))?
+            .is_some_and(|connection| has_token(&connection, b"upgrade"));
+    Ok(Some(vec![u8::from(upgrade)]))
+}
+
+// Whether a comma separated header value lists the token, case-insensitively
+fn has_token(header: &[u8], token: &[u8]) -> bool {
+    header
+        .split(|b| *b == b',')
+        .any(|value| value.trim_ascii().eq_ignore_ascii_case(token))
+}
+
+// Total size of the request headers, counting the bytes of their names and values
+fn request_header_size() -> Result<Option<Vec<u8>>, Status> {
+    let headers = host_get_map(&"request.headers".into()).map_err(|e| {
+        warn!("{e}");
+        Status::NotFound
+    })?;
+    let size: usize = headers
+        .iter()
+        .map(|(name, value)| name.len() + value.len())
+        .sum();
+    Ok(Some((size as i64).to_le_bytes().to_vec()))
+}
+
+fn format_trace_key(connection_id: u64, request_id: &str) -> String {
+    format!("{connection_id}:{request_id}")
+}
+
 fn wasm_prop(tokens: &[&str]) -> Path {
     let mut flat_attr = format!("filter_state.wasm\\.{KUADRANT_NAMESPACE}\\.");
     flat_attr.push_str(tokens.join("\\.").as_str());
@@ -35,6 +260,9 @@ fn wasm_prop(tokens: &[&str]) -> Path {
 #[cfg(test)]
 pub(super) fn host_get_property(path: &Path) -> Result<Option<Vec<u8>>, Status> {
     debug!("get_property: {:?}", path);
+    if test::TEST_ABSENT_PROPERTY.take().as_ref() == Some(path) {
+        return Ok(None);
+    }
     match test::TEST_PROPERTY_VALUE.take() {
         None => Err(Status::NotFound),
         Some((expected_path, data)) => {
@@ -51,6 +279,14 @@ pub fn host_get_map(path: &Path) -> Result<HashMap<String, String>, String> {
             "X-Auth".to_string(),
             "kuadrant".to_string(),
         )])),
+        ["response", "headers"] => Ok(HashMap::from([(
+            "content-type".to_string(),
+            "application/json".to_string(),
+        )])),
+        ["response", "trailers"] => Ok(HashMap::from([
+            ("grpc-status".to_string(), "0".to_string()),
+            ("x-usage-tokens".to_string(), "42".to_string()),
+        ])),
         _ => Err(format!("Unknown map requested {:?}", path)),
     }
 }
@@ -75,6 +311,26 @@ pub fn host_get_map(path: &Path) -> Result<HashMap<String, String>, String> {
             debug!("get_map: {map:#?}");
             Ok(map)
         }
+        // none until the response headers were received
+        ["response", "headers"] => {
+            let map: HashMap<String, String> =
+                proxy_wasm::hostcalls::get_map(proxy_wasm::types::MapType::HttpResponseHeaders)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+            debug!("get_map: {map:#?}");
+            Ok(map)
+        }
+        // none until the response trailers were received
+        ["response", "trailers"] => {
+            let map: HashMap<String, String> =
+                proxy_wasm::hostcalls::get_map(proxy_wasm::types::MapType::HttpResponseTrailers)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+            debug!("get_map: {map:#?}");
+            Ok(map)
+        }
         _ => Err(format!("Unknown map requested {:?}", path)),
     }
 }
@@ -94,6 +350,26 @@ pub(super) fn host_set_property(path: Path, value: Option<&[u8]>) -> Result<(),
 pub(super) fn get_property(path: &Path) -> Result<Option<Vec<u8>>, Status> {
     match *path.tokens() {
         ["source", "remote_address"] => remote_address(),
+        ["kuadrant", "trace_key"] => trace_key(),
+        ["kuadrant", "config_reload_generation"] => Ok(Some(
+            attribute_settings()
+                .config_generation
+                .to_le_bytes()
+                .to_vec(),
+        )),
+        ["response", "body_size"] => Ok(RESPONSE_BODY_SIZE
+            .get()
+            .map(|size| size.to_le_bytes().to_vec())),
+        ["request", "header_size"] => request_header_size(),
+        ["request", "scheme"] => request_scheme(),
+        ["request", "decoded_path"] => request_decoded_path(),
+        ["request", "content_type"] => request_content_type(),
+        ["request", "fragment"] => request_fragment(),
+        ["request", "forwarded_proto"] => forwarded_proto(),
+        ["request", "secure"] => request_secure(),
+        ["request", "websocket_upgrade"] => websocket_upgrade(),
+        ["request", "grpc", field @ ("service" | "method")] => grpc_call_attribute(field),
+        ["request", "geo", field @ ("country" | "asn")] => geo(field),
         ["auth", ..] => host_get_property(&wasm_prop(path.tokens().as_slice())),
         _ => host_get_property(path),
     }
@@ -171,8 +447,247 @@ pub mod test {
 
     thread_local!(
         pub static TEST_PROPERTY_VALUE: Cell<Option<(Path, Vec<u8>)>> = const { Cell::new(None) };
+        pub static TEST_ABSENT_PROPERTY: Cell<Option<Path>> = const { Cell::new(None) };
     );
 
+    #[test]
+    fn grpc_calls_are_parsed_from_the_url_path() {
+        assert_eq!(
+            grpc_call("/envoy.service.ratelimit.v3.RateLimitService/ShouldRateLimit"),
+            Some((
+                "envoy.service.ratelimit.v3.RateLimitService",
+                "ShouldRateLimit"
+            ))
+        );
+        assert_eq!(
+            grpc_call("/Greeter/SayHello"),
+            Some(("Greeter", "SayHello"))
+        );
+        assert_eq!(grpc_call("/toys"), None);
+        assert_eq!(grpc_call("/api/v1/toys"), None);
+        assert_eq!(grpc_call("//SayHello"), None);
+        assert_eq!(grpc_call("Greeter/SayHello"), None);
+
+        assert!(is_grpc_content_type(b"application/grpc"));
+        assert!(is_grpc_content_type(b"application/grpc+proto"));
+        assert!(!is_grpc_content_type(b"application/grpc-web"));
+        assert!(!is_grpc_content_type(b"application/json"));
+    }
+
+    #[test]
+    fn header_tokens_are_matched_case_insensitively() {
+        assert!(has_token(b"Upgrade", b"upgrade"));
+        assert!(has_token(b"keep-alive, Upgrade", b"upgrade"));
+        assert!(has_token(b"websocket", b"websocket"));
+        assert!(!has_token(b"h2c", b"websocket"));
+        assert!(!has_token(b"keep-alive", b"upgrade"));
+        assert!(!has_token(b"", b"upgrade"));
+    }
+
+    #[test]
+    fn geo_is_resolved_from_its_configured_source() {
+        let country: Path = "request.geo.country".into();
+        assert_eq!(get_property(&country), Ok(None));
+
+        let installed = install_attribute_settings(Rc::new(AttributeSettings {
+            geo_country_source: Some("request.headers.x-geo-country".into()),
+            ..Default::default()
+        }));
+        TEST_PROPERTY_VALUE.set(Some((
+            Path::new(vec!["request", "headers", "x-geo-country"]),
+            b"CH".to_vec(),
+        )));
+        assert_eq!(get_property(&country), Ok(Some(b"CH".to_vec())));
+        assert_eq!(get_property(&"request.geo.asn".into()), Ok(None));
+
+        drop(installed);
+        let _installed = install_attribute_settings(Rc::new(AttributeSettings {
+            geo_asn_source: Some("filter_state.geo\\.asn".into()),
+            ..Default::default()
+        }));
+        TEST_PROPERTY_VALUE.set(Some((
+            Path::new(vec!["filter_state", "geo.asn"]),
+            b"AS559".to_vec(),
+        )));
+        assert_eq!(
+            get_property(&"request.geo.asn".into()),
+            Ok(Some(b"AS559".to_vec()))
+        );
+        assert_eq!(get_property(&country), Ok(None));
+    }
+
+    #[test]
+    fn config_reload_generation_is_the_installed_one() {
+        let generation: Path = "kuadrant.config_reload_generation".into();
+        let _first = install_attribute_settings(Rc::new(AttributeSettings {
+            config_generation: 1,
+            ..Default::default()
+        }));
+        assert_eq!(
+            get_property(&generation),
+            Ok(Some(1i64.to_le_bytes().to_vec()))
+        );
+        {
+            let _second = install_attribute_settings(Rc::new(AttributeSettings {
+                config_generation: 2,
+                ..Default::default()
+            }));
+            assert_eq!(
+                get_property(&generation),
+                Ok(Some(2i64.to_le_bytes().to_vec()))
+            );
+        }
+        assert_eq!(
+            get_property(&generation),
+            Ok(Some(1i64.to_le_bytes().to_vec()))
+        );
+    }
+
+    #[test]
+    fn response_body_size_is_absent_until_set() {
+        let body_size: Path = "response.body_size".into();
+        assert_eq!(get_property(&body_size), Ok(None));
+        response_body_size(Some(1024));
+        assert_eq!(
+            get_property(&body_size),
+            Ok(Some(1024i64.to_le_bytes().to_vec()))
+        );
+        response_body_size(None);
+    }
+
+    #[test]
+    fn decoded_path_has_no_query_and_is_decoded() {
+        for (path, decoded) in [
+            ("/toys", "/toys"),
+            ("/toys?color=red", "/toys"),
+            ("/toys#cats", "/toys"),
+            ("/to%79s", "/toys"),
+            ("/admin%2Fusers?x=%2F", "/admin/users"),
+            ("/caf%C3%A9?", "/café"),
+            ("/%FF", "/%FF"),
+            ("", ""),
+        ] {
+            TEST_PROPERTY_VALUE.set(Some(("request.path".into(), path.as_bytes().to_vec())));
+            assert_eq!(
+                get_property(&"request.decoded_path".into()),
+                Ok(Some(decoded.as_bytes().to_vec())),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn scheme_is_forwarded_only_when_trusted() {
+        let forwarded_proto = Path::new(vec!["request", "headers", "x-forwarded-proto"]);
+
+        TEST_PROPERTY_VALUE.set(Some(("request.scheme".into(), b"http".to_vec())));
+        assert_eq!(
+            get_property(&"request.scheme".into()),
+            Ok(Some(b"http".to_vec()))
+        );
+
+        let installed = install_attribute_settings(Rc::new(AttributeSettings {
+            trust_forwarded_proto: true,
+            ..Default::default()
+        }));
+        TEST_PROPERTY_VALUE.set(Some((forwarded_proto.clone(), b"HTTPS, http".to_vec())));
+        assert_eq!(
+            get_property(&"request.scheme".into()),
+            Ok(Some(b"https".to_vec()))
+        );
+        TEST_PROPERTY_VALUE.set(Some((forwarded_proto.clone(), b"https".to_vec())));
+        assert_eq!(get_property(&"request.secure".into()), Ok(Some(vec![1])));
+
+        // without the header, the host's scheme applies
+        TEST_ABSENT_PROPERTY.set(Some(forwarded_proto));
+        TEST_PROPERTY_VALUE.set(Some(("request.scheme".into(), b"http".to_vec())));
+        assert_eq!(
+            get_property(&"request.scheme".into()),
+            Ok(Some(b"http".to_vec()))
+        );
+
+        // the previous settings are back once the installed ones are dropped
+        drop(installed);
+        TEST_PROPERTY_VALUE.set(Some(("request.scheme".into(), b"http".to_vec())));
+        assert_eq!(
+            get_property(&"request.scheme".into()),
+            Ok(Some(b"http".to_vec()))
+        );
+
+        assert_eq!(first_forwarded_proto(b" http "), Some(b"http".to_vec()));
+        assert_eq!(first_forwarded_proto(b", https"), None);
+        assert_eq!(first_forwarded_proto(b""), None);
+    }
+
+    #[test]
+    fn content_type_is_the_media_type_alone() {
+        let content_type_header = Path::new(vec!["request", "headers", "content-type"]);
+        for (header, content_type) in [
+            ("application/json", "application/json"),
+            ("application/json; charset=utf-8", "application/json"),
+            ("Application/JSON;charset=UTF-8", "application/json"),
+            (
+                " multipart/form-data ; boundary=something",
+                "multipart/form-data",
+            ),
+            ("", ""),
+        ] {
+            TEST_PROPERTY_VALUE.set(Some((
+                content_type_header.clone(),
+                header.as_bytes().to_vec(),
+            )));
+            assert_eq!(
+                get_property(&"request.content_type".into()),
+                Ok(Some(content_type.as_bytes().to_vec())),
+                "{header}"
+            );
+        }
+
+        TEST_ABSENT_PROPERTY.set(Some(content_type_header));
+        assert_eq!(
+            get_property(&"request.content_type".into()),
+            Ok(Some(Vec::default()))
+        );
+    }
+
+    #[test]
+    fn fragment_follows_the_hash() {
+        for (path, fragment) in [
+            ("/app#/toys/42", "/toys/42"),
+            ("/graphql?query=x#op=list", "op=list"),
+            ("/toys#", ""),
+            ("/toys?color=red", ""),
+            ("", ""),
+        ] {
+            TEST_PROPERTY_VALUE.set(Some(("request.path".into(), path.as_bytes().to_vec())));
+            assert_eq!(
+                get_property(&"request.fragment".into()),
+                Ok(Some(fragment.as_bytes().to_vec())),
+                "{path}"
+            );
+        }
+
+        TEST_ABSENT_PROPERTY.set(Some("request.path".into()));
+        assert_eq!(
+            get_property(&"request.fragment".into()),
+            Ok(Some(Vec::default()))
+        );
+    }
+
+    #[test]
+    fn trace_key_combines_connection_and_request_ids() {
+        let trace_key = format_trace_key(42, "8f3c6a1e-6d2b-4b7e-9c55-1f2a3b4c5d6e");
+        assert_eq!(trace_key, "42:8f3c6a1e-6d2b-4b7e-9c55-1f2a3b4c5d6e");
+        assert_eq!(
+            trace_key,
+            format_trace_key(42, "8f3c6a1e-6d2b-4b7e-9c55-1f2a3b4c5d6e")
+        );
+        assert_ne!(
+            trace_key,
+            format_trace_key(43, "8f3c6a1e-6d2b-4b7e-9c55-1f2a3b4c5d6e")
+        );
+    }
+
     #[test]
     fn path_tokenizes_with_escaping_basic() {
         let path: Path = r"one\.two..three\\\\.four\\\.\five.".into();
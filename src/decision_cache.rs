@@ -0,0 +1,84 @@
+// Rate limit responses kept per request message, i.e. per domain and descriptors, so that the
+// identical requests within the TTL are answered locally instead of calling the service.
+//
+// Entries expire past the TTL, and the least recently used ones are evicted once as many as the
+// capacity are kept.
+use crate::lru::Lru;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub struct DecisionCache {
+    ttl: Duration,
+    // request message => (response message, when it expires)
+    entries: Lru<Vec<u8>, (Vec<u8>, SystemTime)>,
+}
+
+impl DecisionCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            entries: Lru::new(capacity),
+        }
+    }
+
+    /// The response to the request, unless none was kept or it expired
+    pub fn get(&mut self, request: &[u8], now: SystemTime) -> Option<Vec<u8>> {
+        let (response, expires) = self.entries.get_mut(request)?;
+        if *expires <= now {
+            self.entries.remove(request);
+            return None;
+        }
+        Some(response.clone())
+    }
+
+    pub fn insert(&mut self, request: Vec<u8>, response: Vec<u8>, now: SystemTime) {
+        self.entries.insert(request, (response, now + self.ttl));
+    }
+
+    #[cfg(test)]
+    pub fn cached(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DecisionCache;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn answers_identical_requests_until_expiry() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut cache = DecisionCache::new(Duration::from_secs(1), 16);
+        assert_eq!(cache.get(b"a", now), None);
+
+        cache.insert(b"a".to_vec(), b"over limit".to_vec(), now);
+        assert_eq!(cache.get(b"a", now), Some(b"over limit".to_vec()));
+        assert_eq!(
+            cache.get(b"a", now + Duration::from_millis(999)),
+            Some(b"over limit".to_vec())
+        );
+        assert_eq!(cache.get(b"b", now), None);
+
+        assert_eq!(cache.get(b"a", now + Duration::from_secs(1)), None);
+        assert_eq!(cache.cached(), 0);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut cache = DecisionCache::new(Duration::from_secs(60), 2);
+        cache.insert(b"a".to_vec(), b"1".to_vec(), now);
+        cache.insert(b"b".to_vec(), b"2".to_vec(), now);
+        cache.get(b"a", now);
+
+        // b is the least recently used
+        cache.insert(b"c".to_vec(), b"3".to_vec(), now);
+        assert_eq!(cache.cached(), 2);
+        assert_eq!(cache.get(b"b", now), None);
+        assert_eq!(cache.get(b"a", now), Some(b"1".to_vec()));
+        assert_eq!(cache.get(b"c", now), Some(b"3".to_vec()));
+    }
+}
@@ -1,22 +1,80 @@
 use crate::action_set_index::ActionSetIndex;
+use crate::configuration::SelectionMode;
+use crate::data::{
+    install_attribute_settings, response_body_size, AttributeSettings, InstalledAttributeSettings,
+    Predicate,
+};
 use crate::filter::operations::{
     GrpcMessageReceiverOperation, GrpcMessageSenderOperation, HeadersOperation, Operation,
+    ResponseHeaders,
+};
+use crate::metrics::Metrics;
+use crate::runtime_action_set::{InFlight, RuntimeActionSet};
+use crate::service::{
+    grpc_timeout, remaining_budget, GrpcErrResponse, GrpcRequest, HeaderResolver, Headers,
+    GRPC_TIMEOUT_HEADER,
 };
-use crate::runtime_action_set::RuntimeActionSet;
-use crate::service::{GrpcErrResponse, GrpcRequest, HeaderResolver};
 use log::{debug, warn};
 use proxy_wasm::traits::{Context, HttpContext};
 use proxy_wasm::types::{Action, Status};
+use std::collections::VecDeque;
 use std::mem;
 use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+const ERROR_HEADER: &str = "x-kuadrant-error";
+const EXPLAIN_HEADER: &str = "x-kuadrant-explain";
+// gRPC status of the calls that didn't complete within the service's timeout
+const GRPC_DEADLINE_EXCEEDED: u32 = 4;
+const OVERSIZED_RESPONSES_METRIC: &str = "kuadrant.grpc_response_oversized";
+const SHADOW_DENIALS_METRIC: &str = "kuadrant.denied_shadow";
+const GRPC_DURATION_METRIC: &str = "kuadrant.grpc_duration_ms";
+// failures let through as per `FailureMode::Passthrough`
+const ERRORS_METRIC: &str = "kuadrant.errors";
+
+// The plugin configuration the filters of a root context share, replaced as a whole once
+// another one is applied
+#[derive(Default)]
+pub(crate) struct FilterConfig {
+    pub index: ActionSetIndex,
+    pub decision_header: Option<String>,
+    pub expose_errors: bool,
+    pub explain: bool,
+    pub trace_when: Option<Rc<Predicate>>,
+    pub request_deadline: Option<Duration>,
+    pub grpc_duration_metrics: bool,
+    pub selection_mode: SelectionMode,
+    pub attributes: Rc<AttributeSettings>,
+}
 
 pub(crate) struct KuadrantFilter {
     context_id: u32,
-    index: Rc<ActionSetIndex>,
+    config: Rc<FilterConfig>,
     header_resolver: Rc<HeaderResolver>,
+    metrics: Rc<Metrics>,
 
+    // when the request reached the filter, only taken with a deadline to count from
+    request_started: Option<SystemTime>,
+    // when the pending gRPC call was dispatched, only taken when its duration is recorded
+    grpc_dispatched: Option<SystemTime>,
+    selected_action_set: Option<String>,
+    // the action sets yet to run once the selected one allows the request, whose conditions
+    // are only evaluated then, as per `SelectionMode::All`
+    pending_action_sets: VecDeque<Rc<RuntimeActionSet>>,
+    // the action set whose deferred actions are yet to run on the response
+    deferred_action_set: Option<Rc<RuntimeActionSet>>,
+    in_response_phase: bool,
+    response_paused: bool,
+    // bytes of the response body so far, only counted for the actions awaiting it
+    response_body_size: i64,
+    response_body_complete: bool,
+    // released once the filter is dropped, i.e. when the request completes
+    _in_flight: Vec<InFlight>,
+    // the action sets that counted the request on its downstream connection, and the connection
+    counted_connections: Vec<(Rc<RuntimeActionSet>, u64)>,
+    last_error: Option<String>,
     grpc_message_receiver_operation: Option<GrpcMessageReceiverOperation>,
-    headers_operations: Vec<HeadersOperation>,
+    response_headers: ResponseHeaders,
 }
 
 impl Context for KuadrantFilter {
@@ -25,70 +83,187 @@ impl Context for KuadrantFilter {
             "#{} on_grpc_call_response: received gRPC call response: token: {token_id}, status: {status_code}",
             self.context_id
         );
+        let _attributes = self.install_attribute_settings();
         let receiver = mem::take(&mut self.grpc_message_receiver_operation)
             .expect("We need an operation pending a gRPC response");
+        self.record_grpc_duration(receiver.service_type());
+        if self.response_body_complete {
+            response_body_size(Some(self.response_body_size));
+        }
 
         let mut ops = Vec::new();
 
-        if status_code != Status::Ok as u32 {
-            ops.push(receiver.fail());
+        if status_code == GRPC_DEADLINE_EXCEEDED {
+            self.last_error = Some("gRPC call timed out".to_string());
+            ops.extend(receiver.time_out());
+        } else if status_code != Status::Ok as u32 {
+            self.last_error = Some(format!("gRPC call failed with status {status_code}"));
+            ops.extend(receiver.fail_with_status(status_code));
+        } else if let Some(max) = receiver.max_response_size().filter(|max| resp_size > *max) {
+            self.last_error = Some(format!(
+                "gRPC call response of {resp_size} bytes exceeds the maximum of {max}"
+            ));
+            self.metrics.increment(OVERSIZED_RESPONSES_METRIC, &[]);
+            ops.extend(receiver.fail());
         } else if let Some(response_body) = self.get_grpc_call_response_body(0, resp_size) {
+            if receiver.caches_decisions() {
+                receiver.cache_response(&response_body, self.get_current_time());
+            }
             ops.extend(receiver.digest_grpc_response(&response_body));
         } else {
-            ops.push(receiver.fail());
+            self.last_error = Some("gRPC call response has no body".to_string());
+            ops.extend(receiver.fail());
         }
 
-        ops.into_iter().for_each(|op| {
-            self.handle_operation(op);
-        })
+        self.handle_operations(ops);
+        response_body_size(None);
     }
 }
 
 impl HttpContext for KuadrantFilter {
     fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
         debug!("#{} on_http_request_headers", self.context_id);
+        let _attributes = self.install_attribute_settings();
 
         #[cfg(feature = "debug-host-behaviour")]
         crate::data::debug_all_well_known_attributes();
 
-        if let Some(action_sets) = self
+        let authority = self.request_authority();
+        let config = Rc::clone(&self.config);
+        let mut candidates = config
             .index
-            .get_longest_match_action_sets(self.request_authority().as_ref())
-        {
-            if let Some(action_set) = action_sets
-                .iter()
-                .find(|action_set| action_set.conditions_apply(/* self */))
-            {
-                debug!(
-                    "#{} action_set selected {}",
-                    self.context_id, action_set.name
-                );
-                return self.start_flow(Rc::clone(action_set));
+            .get_longest_match_action_sets(authority.as_ref())
+            .into_iter()
+            .flatten()
+            .chain(config.index.get_host_agnostic_action_sets());
+        let selected = candidates
+            .find(|action_set| action_set.conditions_apply(/* self */))
+            .cloned();
+        if let Some(action_set) = selected {
+            debug!(
+                "#{} action_set selected {}",
+                self.context_id, action_set.name
+            );
+            if self.config.selection_mode == SelectionMode::All {
+                self.pending_action_sets = candidates.cloned().collect();
             }
+            return self.start_flow(action_set);
         }
         Action::Continue
     }
 
     fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
         debug!("#{} on_http_response_headers", self.context_id);
-        let headers_operations = mem::take(&mut self.headers_operations);
-        for op in headers_operations {
-            for (header, value) in &op.headers() {
-                self.add_http_response_header(header, value)
+        let _attributes = self.install_attribute_settings();
+        if let Some(action_set) = self.deferred_action_set.clone() {
+            self.in_response_phase = true;
+            // kept for the actions awaiting the response body, if any
+            if !action_set.has_response_body_actions() {
+                self.deferred_action_set = None;
+            }
+            let op = match action_set.find_first_response_grpc_request() {
+                Ok(None) => None,
+                Ok(Some(indexed_req)) => Some(Operation::SendGrpcRequest(
+                    GrpcMessageSenderOperation::new(action_set, indexed_req),
+                )),
+                Err(grpc_err_resp) => Some(Operation::Die(grpc_err_resp)),
+            };
+            if let Some(op) = op {
+                if let Action::Pause = self.handle_operation(op) {
+                    self.response_paused = true;
+                    return Action::Pause;
+                }
             }
         }
+        self.commit_response_headers();
         Action::Continue
     }
+
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        if !self.awaits_response_body() {
+            return Action::Continue;
+        }
+        self.response_body_size += body_size as i64;
+        if !end_of_stream {
+            return Action::Continue;
+        }
+        debug!(
+            "#{} on_http_response_body: {} bytes",
+            self.context_id, self.response_body_size
+        );
+        self.complete_response_body()
+    }
+
+    fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
+        if !self.awaits_response_body() {
+            return Action::Continue;
+        }
+        // a response with trailers, e.g. gRPC's, has no last chunk of its body to end it
+        debug!(
+            "#{} on_http_response_trailers: {} bytes",
+            self.context_id, self.response_body_size
+        );
+        self.complete_response_body()
+    }
+
+    fn on_log(&mut self) {
+        if self.counted_connections.is_empty() || !self.closes_connection() {
+            return;
+        }
+        for (action_set, connection_id) in self.counted_connections.drain(..) {
+            action_set.forget_connection(connection_id);
+        }
+    }
 }
 
 impl KuadrantFilter {
+    // Resolves the attributes as per this filter's configuration for the time of a callback,
+    // rather than the one of the last root context configured
+    fn install_attribute_settings(&self) -> InstalledAttributeSettings {
+        install_attribute_settings(Rc::clone(&self.config.attributes))
+    }
+
     fn start_flow(&mut self, action_set: Rc<RuntimeActionSet>) -> Action {
+        self.selected_action_set = Some(action_set.name.clone());
+        if self.config.request_deadline.is_some() && self.request_started.is_none() {
+            self.request_started = Some(self.get_current_time());
+        }
+        if self.config.explain
+            && self.get_http_request_header(EXPLAIN_HEADER).as_deref() == Some("true")
+        {
+            debug!("#{} explaining instead of proxying", self.context_id);
+            let explanation = action_set.explain().to_string();
+            self.send_http_response(
+                200,
+                vec![("content-type", "application/json")],
+                Some(explanation.as_bytes()),
+            );
+            return Action::Continue;
+        }
+        if let Err(grpc_err_resp) = action_set.check_requirements() {
+            return self.handle_operation(Operation::Die(grpc_err_resp));
+        }
+        match action_set.count_connection_request() {
+            Ok(Some(connection_id)) => self
+                .counted_connections
+                .push((Rc::clone(&action_set), connection_id)),
+            Ok(None) => {}
+            Err(grpc_err_resp) => return self.handle_operation(Operation::Die(grpc_err_resp)),
+        }
+        match action_set.enter() {
+            Ok(in_flight) => self._in_flight.push(in_flight),
+            Err(grpc_err_resp) => return self.handle_operation(Operation::Die(grpc_err_resp)),
+        }
+        if action_set.has_deferred_actions() {
+            self.deferred_action_set = Some(Rc::clone(&action_set));
+        }
         let grpc_request = action_set.find_first_grpc_request();
         let op = match grpc_request {
-            None => Operation::Done(),
-            Some(indexed_req) => {
+            Ok(None) => Operation::Done(),
+            Ok(Some(indexed_req)) => {
                 Operation::SendGrpcRequest(GrpcMessageSenderOperation::new(action_set, indexed_req))
             }
+            Err(grpc_err_resp) => Operation::Die(grpc_err_resp),
         };
         self.handle_operation(op)
     }
@@ -97,17 +272,41 @@ impl KuadrantFilter {
         match operation {
             Operation::SendGrpcRequest(sender_op) => {
                 debug!("handle_operation: SendGrpcRequest");
-                let next_op = {
+                let next_ops = {
                     let (req, receiver_op) = sender_op.build_receiver_operation();
-                    match self.send_grpc_request(req) {
-                        Ok(_token) => Operation::AwaitGrpcResponse(receiver_op),
-                        Err(status) => {
-                            debug!("handle_operation: failed to send grpc request `{status:?}`");
-                            receiver_op.fail()
+                    let cached = receiver_op
+                        .caches_decisions()
+                        .then(|| receiver_op.cached_response(self.get_current_time()))
+                        .flatten();
+                    let budget = self.remaining_budget();
+                    if let Some(response) = cached {
+                        debug!("handle_operation: decision cached, not sending grpc request");
+                        receiver_op.digest_grpc_response(&response)
+                    } else if budget.is_some_and(|budget| budget < Duration::from_millis(1)) {
+                        // a dispatch timeout under a millisecond would be no timeout at all
+                        debug!("handle_operation: request deadline exceeded");
+                        self.last_error = Some("request deadline exceeded".to_string());
+                        receiver_op.time_out()
+                    } else {
+                        match self.send_grpc_request(req, budget) {
+                            Ok(_token) => {
+                                if self.config.grpc_duration_metrics {
+                                    self.grpc_dispatched = Some(self.get_current_time());
+                                }
+                                vec![Operation::AwaitGrpcResponse(receiver_op)]
+                            }
+                            Err(status) => {
+                                debug!(
+                                    "handle_operation: failed to send grpc request `{status:?}`"
+                                );
+                                self.last_error =
+                                    Some(format!("failed to send gRPC request: {status:?}"));
+                                receiver_op.fail()
+                            }
                         }
                     }
                 };
-                self.handle_operation(next_op)
+                self.handle_operations(next_ops)
             }
             Operation::AwaitGrpcResponse(receiver_op) => {
                 debug!("handle_operation: AwaitGrpcResponse");
@@ -116,7 +315,38 @@ impl KuadrantFilter {
             }
             Operation::AddHeaders(header_op) => {
                 debug!("handle_operation: AddHeaders");
-                self.headers_operations.push(header_op);
+                if header_op.is_degraded() {
+                    warn!(
+                        "#{} failure let through, enforcement degraded: {}",
+                        self.context_id,
+                        self.last_error.as_deref().unwrap_or("unknown error")
+                    );
+                    self.metrics.increment(ERRORS_METRIC, &[]);
+                }
+                if let Err(header_op) = self.response_headers.push(header_op) {
+                    warn!(
+                        "#{} response headers already sent, dropping {:?}",
+                        self.context_id,
+                        header_op.headers()
+                    );
+                }
+                Action::Continue
+            }
+            Operation::ShadowDeny(die_op) => {
+                debug!(
+                    "handle_operation: ShadowDeny, not enforced: {}",
+                    die_op.status_code()
+                );
+                self.metrics.increment(SHADOW_DENIALS_METRIC, &[]);
+                Action::Continue
+            }
+            Operation::Die(die_op) if self.in_response_phase => {
+                // the deferred actions only count, the response is already made
+                debug!(
+                    "handle_operation: Die, ignored on the response: {}",
+                    die_op.status_code()
+                );
+                self.finish_response();
                 Action::Continue
             }
             Operation::Die(die_op) => {
@@ -124,20 +354,147 @@ impl KuadrantFilter {
                 self.die(die_op);
                 Action::Continue
             }
+            Operation::Done() if self.in_response_phase => {
+                debug!("handle_operation: Done");
+                self.finish_response();
+                Action::Continue
+            }
             Operation::Done() => {
                 debug!("handle_operation: Done");
+                if let Some(action_set) = self.next_action_set() {
+                    debug!(
+                        "#{} action_set selected {}",
+                        self.context_id, action_set.name
+                    );
+                    return self.start_flow(action_set);
+                }
+                let annotations = self.annotation_headers("allow");
+                if !annotations.is_empty() {
+                    self.handle_operation(Operation::AddHeaders(HeadersOperation::new(
+                        annotations,
+                    )));
+                }
                 self.resume_http_request();
                 Action::Continue
             }
         }
     }
 
+    // The next of the pending action sets whose conditions apply, if any
+    fn next_action_set(&mut self) -> Option<Rc<RuntimeActionSet>> {
+        while let Some(action_set) = self.pending_action_sets.pop_front() {
+            if action_set.conditions_apply() {
+                return Some(action_set);
+            }
+        }
+        None
+    }
+
+    fn handle_operations(&mut self, operations: Vec<Operation>) -> Action {
+        operations
+            .into_iter()
+            .fold(Action::Continue, |_, op| self.handle_operation(op))
+    }
+
+    fn commit_response_headers(&mut self) {
+        for op in self.response_headers.commit() {
+            for (header, value) in &op.headers() {
+                self.add_http_response_header(header, value)
+            }
+        }
+    }
+
+    fn finish_response(&mut self) {
+        self.commit_response_headers();
+        if self.response_body_complete && self.deferred_action_set.is_some() {
+            // the body went through while the actions on the response headers ran, the ones
+            // awaiting it finish the response instead
+            self.run_response_body_actions();
+            return;
+        }
+        if self.response_paused {
+            self.resume_http_response();
+        }
+    }
+
+    // Whether either side asked for the downstream connection to close after this exchange, the
+    // only close the filter gets to know of
+    fn closes_connection(&self) -> bool {
+        let closes = |value: Option<String>| {
+            value.is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case("close"))
+            })
+        };
+        closes(self.get_http_request_header("connection"))
+            || closes(self.get_http_response_header("connection"))
+    }
+
+    fn awaits_response_body(&self) -> bool {
+        self.deferred_action_set
+            .as_ref()
+            .is_some_and(|action_set| action_set.has_response_body_actions())
+    }
+
+    fn complete_response_body(&mut self) -> Action {
+        self.response_body_complete = true;
+        let _attributes = self.install_attribute_settings();
+        if self.grpc_message_receiver_operation.is_some() {
+            // the actions on the response headers are yet to complete, see `finish_response`
+            self.response_paused = true;
+            return Action::Pause;
+        }
+        if let Action::Pause = self.run_response_body_actions() {
+            self.response_paused = true;
+            return Action::Pause;
+        }
+        Action::Continue
+    }
+
+    fn run_response_body_actions(&mut self) -> Action {
+        let Some(action_set) = self.deferred_action_set.take() else {
+            return Action::Continue;
+        };
+        response_body_size(Some(self.response_body_size));
+        let op = match action_set.find_first_response_body_grpc_request() {
+            Ok(None) => Operation::Done(),
+            Ok(Some(indexed_req)) => {
+                Operation::SendGrpcRequest(GrpcMessageSenderOperation::new(action_set, indexed_req))
+            }
+            Err(grpc_err_resp) => Operation::Die(grpc_err_resp),
+        };
+        let action = self.handle_operation(op);
+        response_body_size(None);
+        action
+    }
+
     fn die(&mut self, die: GrpcErrResponse) {
-        self.send_http_response(
-            die.status_code(),
-            die.headers(),
-            Some(die.body().as_bytes()),
+        // a locally denied request's response doesn't count
+        self.deferred_action_set = None;
+        let annotations = self.annotation_headers("deny");
+        let mut headers = die.headers();
+        headers.extend(
+            annotations
+                .iter()
+                .map(|(header, value)| (header.as_str(), value.as_str())),
         );
+        self.send_http_response(die.status_code(), headers, Some(die.body().as_bytes()));
+    }
+
+    // `{action set};{decision}` in the configured decision header, and why an action failed
+    // when errors are exposed, if opted in
+    fn annotation_headers(&self, decision: &str) -> Headers {
+        let mut headers = Vec::default();
+        if let (Some(header), Some(action_set)) =
+            (&self.config.decision_header, &self.selected_action_set)
+        {
+            headers.push((header.to_string(), format!("{action_set};{decision}")));
+        }
+        if let (true, Some(error)) = (self.config.expose_errors, &self.last_error) {
+            headers.push((ERROR_HEADER.to_string(), error.replace(['\r', '\n'], " ")));
+        }
+        headers
     }
 
     fn request_authority(&self) -> String {
@@ -153,13 +510,45 @@ impl KuadrantFilter {
         }
     }
 
-    fn send_grpc_request(&self, req: GrpcRequest) -> Result<u32, Status> {
+    // What's left of the request's deadline, if one is configured
+    fn remaining_budget(&self) -> Option<Duration> {
+        let deadline = self.config.request_deadline?;
+        let started = self.request_started?;
+        Some(remaining_budget(deadline, started, self.get_current_time()))
+    }
+
+    fn record_grpc_duration(&mut self, service_type: &str) {
+        let Some(dispatched) = self.grpc_dispatched.take() else {
+            return;
+        };
+        let elapsed = self
+            .get_current_time()
+            .duration_since(dispatched)
+            .unwrap_or_default();
+        self.metrics.record(
+            GRPC_DURATION_METRIC,
+            &[("service", service_type)],
+            elapsed.as_millis() as u64,
+        );
+    }
+
+    fn send_grpc_request(&self, req: GrpcRequest, budget: Option<Duration>) -> Result<u32, Status> {
+        let grpc_timeout = budget.map(grpc_timeout);
         let headers = self
             .header_resolver
             .get_with_ctx(self)
             .iter()
             .map(|(header, value)| (*header, value.as_slice()))
+            .chain(
+                grpc_timeout
+                    .iter()
+                    .map(|timeout| (GRPC_TIMEOUT_HEADER, timeout.as_bytes())),
+            )
             .collect();
+        let timeout = match budget {
+            Some(budget) => budget.min(req.timeout()),
+            None => req.timeout(),
+        };
 
         self.dispatch_grpc_call(
             req.upstream_name(),
@@ -167,21 +556,33 @@ impl KuadrantFilter {
             req.method_name(),
             headers,
             req.message(),
-            req.timeout(),
+            timeout,
         )
     }
 
-    pub fn new(
-        context_id: u32,
-        index: Rc<ActionSetIndex>,
-        header_resolver: Rc<HeaderResolver>,
-    ) -> Self {
+    pub fn new(context_id: u32, config: Rc<FilterConfig>, metrics: Rc<Metrics>) -> Self {
+        let header_resolver = Rc::new(HeaderResolver::new_with_condition(
+            config.trace_when.clone(),
+        ));
         Self {
             context_id,
-            index,
+            config,
             header_resolver,
+            metrics,
+            request_started: None,
+            grpc_dispatched: None,
+            selected_action_set: None,
+            pending_action_sets: VecDeque::default(),
+            deferred_action_set: None,
+            in_response_phase: false,
+            response_paused: false,
+            response_body_size: 0,
+            response_body_complete: false,
+            _in_flight: Vec::default(),
+            counted_connections: Vec::default(),
+            last_error: None,
             grpc_message_receiver_operation: None,
-            headers_operations: Vec::default(),
+            response_headers: ResponseHeaders::default(),
         }
     }
 }
@@ -1,21 +1,31 @@
-use crate::configuration::FailureMode;
+use crate::configuration::{FailureMode, OnTimeout};
+use crate::envoy::StatusCode;
 use crate::filter::operations::Operation::SendGrpcRequest;
 use crate::runtime_action_set::RuntimeActionSet;
 use crate::service::{GrpcErrResponse, GrpcRequest, Headers, IndexedGrpcRequest};
 use std::rc::Rc;
+use std::time::SystemTime;
 
 pub enum Operation {
     SendGrpcRequest(GrpcMessageSenderOperation),
     AwaitGrpcResponse(GrpcMessageReceiverOperation),
     AddHeaders(HeadersOperation),
+    // The denial of a dry run action, counted but not enforced
+    ShadowDeny(GrpcErrResponse),
     Die(GrpcErrResponse),
     // Done indicates that we have no more operations and can resume the http request flow
     Done(),
 }
 
+// gRPC statuses of the calls worth sending again: UNAVAILABLE, ABORTED and RESOURCE_EXHAUSTED
+const TRANSIENT_GRPC_STATUSES: [u32; 3] = [14, 10, 8];
+
 pub struct GrpcMessageSenderOperation {
     runtime_action_set: Rc<RuntimeActionSet>,
     grpc_request: IndexedGrpcRequest,
+    retried: bool,
+    // times the call was already sent again after a transient status
+    retries: u32,
 }
 
 impl GrpcMessageSenderOperation {
@@ -26,28 +36,71 @@ impl GrpcMessageSenderOperation {
         Self {
             runtime_action_set,
             grpc_request: indexed_request,
+            retried: false,
+            retries: 0,
         }
     }
 
     pub fn build_receiver_operation(self) -> (GrpcRequest, GrpcMessageReceiverOperation) {
         let index = self.grpc_request.index();
-        (
-            self.grpc_request.request(),
-            GrpcMessageReceiverOperation::new(self.runtime_action_set, index),
-        )
+        let request = self.grpc_request.request();
+        let caches_decisions = self.runtime_action_set.runtime_actions[index].caches_decisions();
+        let mut receiver_op =
+            GrpcMessageReceiverOperation::new(self.runtime_action_set, index, request.clone());
+        receiver_op.retried = self.retried;
+        receiver_op.retries = self.retries;
+        receiver_op.caches_decisions = caches_decisions;
+        (request, receiver_op)
     }
 }
 
 pub struct GrpcMessageReceiverOperation {
     runtime_action_set: Rc<RuntimeActionSet>,
     current_index: usize,
+    retried: bool,
+    retries: u32,
+    // the request sent, as is sent again on a retry, and the key of its cached decision
+    request: GrpcRequest,
+    caches_decisions: bool,
 }
 
 impl GrpcMessageReceiverOperation {
-    pub fn new(runtime_action_set: Rc<RuntimeActionSet>, current_index: usize) -> Self {
+    pub fn new(
+        runtime_action_set: Rc<RuntimeActionSet>,
+        current_index: usize,
+        request: GrpcRequest,
+    ) -> Self {
         Self {
             runtime_action_set,
             current_index,
+            retried: false,
+            retries: 0,
+            request,
+            caches_decisions: false,
+        }
+    }
+
+    pub fn caches_decisions(&self) -> bool {
+        self.caches_decisions
+    }
+
+    // The request's message, only when the action caches its decisions
+    fn cache_key(&self) -> Option<&[u8]> {
+        self.caches_decisions
+            .then(|| self.request.message())
+            .flatten()
+    }
+
+    /// The response to an identical request, if its decision is cached
+    pub fn cached_response(&self, now: SystemTime) -> Option<Vec<u8>> {
+        let request = self.cache_key()?;
+        self.runtime_action_set.runtime_actions[self.current_index].cached_response(request, now)
+    }
+
+    pub fn cache_response(&self, msg: &[u8], now: SystemTime) {
+        if let Some(request) = self.cache_key() {
+            self.runtime_action_set.runtime_actions[self.current_index]
+                .cache_response(request, msg, now);
         }
     }
 
@@ -57,9 +110,11 @@ impl GrpcMessageReceiverOperation {
             .process_grpc_response(self.current_index, msg);
 
         match result {
-            Ok((next_msg, headers)) => {
+            Ok((next_msg, headers, degraded)) => {
                 let mut operations = Vec::new();
-                if !headers.is_empty() {
+                if degraded {
+                    operations.push(Operation::AddHeaders(HeadersOperation::degraded(headers)))
+                } else if !headers.is_empty() {
                     operations.push(Operation::AddHeaders(HeadersOperation::new(headers)))
                 }
                 operations.push(match next_msg {
@@ -71,37 +126,233 @@ impl GrpcMessageReceiverOperation {
                 });
                 operations
             }
+            Err(grpc_err_resp)
+                if grpc_err_resp.status_code() == StatusCode::TooManyRequests as u32
+                    && self.runtime_action_set.runtime_actions[self.current_index].is_dry_run() =>
+            {
+                vec![Operation::ShadowDeny(grpc_err_resp), self.skip()]
+            }
             Err(grpc_err_resp) => vec![Operation::Die(grpc_err_resp)],
         }
     }
 
-    pub fn fail(self) -> Operation {
+    /// The size past which the response is rejected rather than read, if any
+    pub fn max_response_size(&self) -> Option<usize> {
+        self.runtime_action_set.runtime_actions[self.current_index].get_max_response_size()
+    }
+
+    /// The kind of service the response is awaited from
+    pub fn service_type(&self) -> &'static str {
+        self.runtime_action_set.runtime_actions[self.current_index].service_type()
+    }
+
+    pub fn fail(self) -> Vec<Operation> {
         match self.runtime_action_set.runtime_actions[self.current_index].get_failure_mode() {
-            FailureMode::Deny => Operation::Die(GrpcErrResponse::new_internal_server_error()),
-            FailureMode::Allow => match self
-                .runtime_action_set
-                .find_next_grpc_request(self.current_index + 1)
-            {
-                None => Operation::Done(),
-                Some(indexed_req) => Operation::SendGrpcRequest(GrpcMessageSenderOperation::new(
-                    self.runtime_action_set,
-                    indexed_req,
-                )),
-            },
+            FailureMode::Deny => vec![Operation::Die(GrpcErrResponse::new_internal_server_error())],
+            FailureMode::Allow => vec![self.skip()],
+            FailureMode::Passthrough => {
+                let headers =
+                    self.runtime_action_set.runtime_actions[self.current_index].degraded_headers();
+                vec![
+                    Operation::AddHeaders(HeadersOperation::degraded(headers)),
+                    self.skip(),
+                ]
+            }
+        }
+    }
+
+    /// Sends the call again on a transient status, as many times as the service allows, before
+    /// failing as per the failure mode
+    pub fn fail_with_status(self, status_code: u32) -> Vec<Operation> {
+        let retries = self.runtime_action_set.runtime_actions[self.current_index].get_retries();
+        if TRANSIENT_GRPC_STATUSES.contains(&status_code) && self.retries < retries {
+            let (retried, retries) = (self.retried, self.retries + 1);
+            vec![self.resend(retried, retries)]
+        } else {
+            self.fail()
+        }
+    }
+
+    pub fn time_out(self) -> Vec<Operation> {
+        match self.runtime_action_set.runtime_actions[self.current_index].get_on_timeout() {
+            None => self.fail(),
+            Some(OnTimeout::Deny) => {
+                vec![Operation::Die(GrpcErrResponse::new_internal_server_error())]
+            }
+            Some(OnTimeout::Allow) => vec![self.skip()],
+            Some(OnTimeout::Retry) if !self.retried => {
+                let retries = self.retries;
+                vec![self.resend(true, retries)]
+            }
+            Some(OnTimeout::Retry) => self.fail(),
+        }
+    }
+
+    // Sends the very same request again, rather than building it anew
+    fn resend(self, retried: bool, retries: u32) -> Operation {
+        let indexed_req = IndexedGrpcRequest::new(self.current_index, self.request);
+        let mut sender_op = GrpcMessageSenderOperation::new(self.runtime_action_set, indexed_req);
+        sender_op.retried = retried;
+        sender_op.retries = retries;
+        Operation::SendGrpcRequest(sender_op)
+    }
+
+    fn skip(self) -> Operation {
+        let phase = self.runtime_action_set.phase_of(self.current_index);
+        match self
+            .runtime_action_set
+            .find_next_grpc_request(self.current_index + 1, phase)
+        {
+            Ok(None) => Operation::Done(),
+            Ok(Some(indexed_req)) => Operation::SendGrpcRequest(GrpcMessageSenderOperation::new(
+                self.runtime_action_set,
+                indexed_req,
+            )),
+            Err(grpc_err_resp) => Operation::Die(grpc_err_resp),
         }
     }
 }
 
 pub struct HeadersOperation {
     headers: Headers,
+    // whether the headers flag a failure let through as per `FailureMode::Passthrough`
+    degraded: bool,
 }
 
 impl HeadersOperation {
     pub fn new(headers: Headers) -> Self {
-        Self { headers }
+        Self {
+            headers,
+            degraded: false,
+        }
+    }
+
+    pub fn degraded(headers: Headers) -> Self {
+        Self {
+            headers,
+            degraded: true,
+        }
     }
 
     pub fn headers(self) -> Headers {
         self.headers
     }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+}
+
+// Response headers to add, collected until the response headers get sent downstream
+#[derive(Default)]
+pub struct ResponseHeaders {
+    operations: Vec<HeadersOperation>,
+    committed: bool,
+}
+
+impl ResponseHeaders {
+    /// Queues the headers to be added, unless the response headers were already sent, in
+    /// which case they are handed back as they can't be applied anymore
+    pub fn push(&mut self, operation: HeadersOperation) -> Result<(), HeadersOperation> {
+        if self.committed {
+            return Err(operation);
+        }
+        self.operations.push(operation);
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Vec<HeadersOperation> {
+        self.committed = true;
+        std::mem::take(&mut self.operations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::{Action, ActionSet, Service};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn retries_send_the_very_same_request() {
+        let action: Action = serde_json::from_str(r#"{"service": "limitador", "scope": "a"}"#)
+            .expect("a valid action");
+        let service: Service = serde_json::from_str(
+            r#"{"type": "ratelimit", "endpoint": "limitador-cluster", "retries": 1}"#,
+        )
+        .expect("a valid service");
+        let action_set = ActionSet::new("some_name".to_owned(), Default::default(), vec![action]);
+        let runtime_action_set = RuntimeActionSet::new(
+            &action_set,
+            &HashMap::from([("limitador".to_string(), service)]),
+        )
+        .expect("a valid action set");
+        let request = GrpcRequest::new(
+            "limitador-cluster",
+            "envoy.service.ratelimit.v3.RateLimitService",
+            "ShouldRateLimit",
+            Duration::from_secs(1),
+            Some(b"as first sent".to_vec()),
+        );
+        let receiver_op =
+            GrpcMessageReceiverOperation::new(Rc::new(runtime_action_set), 0, request);
+
+        let Some(Operation::SendGrpcRequest(sender_op)) =
+            receiver_op.fail_with_status(14).into_iter().next()
+        else {
+            panic!("the request should be sent again");
+        };
+        let (request, receiver_op) = sender_op.build_receiver_operation();
+        assert_eq!(request.message(), Some(&b"as first sent"[..]));
+
+        // out of retries
+        assert!(matches!(
+            receiver_op.fail_with_status(14).as_slice(),
+            [Operation::Die(_)]
+        ));
+    }
+
+    #[test]
+    fn degraded_headers_are_told_apart() {
+        let headers = vec![("x-kuadrant-degraded".to_string(), "true".to_string())];
+        assert!(HeadersOperation::degraded(headers.clone()).is_degraded());
+        // whatever the headers, only a failure let through is degraded
+        assert!(!HeadersOperation::new(headers).is_degraded());
+        assert!(!HeadersOperation::new(Vec::default()).is_degraded());
+    }
+
+    #[test]
+    fn headers_are_queued_until_committed() {
+        let mut response_headers = ResponseHeaders::default();
+        assert!(response_headers
+            .push(HeadersOperation::new(vec![(
+                "x-foo".to_string(),
+                "bar".to_string()
+            )]))
+            .is_ok());
+
+        let operations = response_headers.commit();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(
+            operations
+                .into_iter()
+                .flat_map(HeadersOperation::headers)
+                .collect::<Headers>(),
+            vec![("x-foo".to_string(), "bar".to_string())]
+        );
+    }
+
+    #[test]
+    fn headers_are_rejected_once_committed() {
+        let mut response_headers = ResponseHeaders::default();
+        assert!(response_headers.commit().is_empty());
+
+        let rejected = response_headers.push(HeadersOperation::new(vec![(
+            "x-foo".to_string(),
+            "bar".to_string(),
+        )]));
+        assert!(rejected.is_err());
+        assert!(response_headers.commit().is_empty());
+    }
 }
@@ -1,7 +1,9 @@
 use crate::action_set_index::ActionSetIndex;
 use crate::configuration::PluginConfiguration;
-use crate::filter::kuadrant_filter::KuadrantFilter;
-use crate::service::HeaderResolver;
+use crate::data::{AttributeSettings, Predicate};
+use crate::filter::kuadrant_filter::{FilterConfig, KuadrantFilter};
+use crate::metrics::Metrics;
+use crate::runtime_config::ConfigSummary;
 use const_format::formatcp;
 use log::{debug, error, info};
 use proxy_wasm::traits::{Context, HttpContext, RootContext};
@@ -13,10 +15,14 @@ const WASM_SHIM_PROFILE: &str = env!("WASM_SHIM_PROFILE");
 const WASM_SHIM_FEATURES: &str = env!("WASM_SHIM_FEATURES");
 const WASM_SHIM_GIT_HASH: &str = env!("WASM_SHIM_GIT_HASH");
 const WASM_SHIM_HEADER: &str = "Kuadrant wasm module";
+const CONFIG_RELOADS_METRIC: &str = "kuadrant.config_reloads";
 
 pub struct FilterRoot {
     pub context_id: u32,
-    pub action_set_index: Rc<ActionSetIndex>,
+    pub config: Rc<FilterConfig>,
+    pub metrics: Rc<Metrics>,
+    // configurations applied so far
+    pub config_generation: i64,
 }
 
 impl RootContext for FilterRoot {
@@ -34,11 +40,10 @@ impl RootContext for FilterRoot {
 
     fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
         debug!("#{} create_http_context", context_id);
-        let header_resolver = Rc::new(HeaderResolver::new());
         Some(Box::new(KuadrantFilter::new(
             context_id,
-            Rc::clone(&self.action_set_index),
-            header_resolver,
+            Rc::clone(&self.config),
+            Rc::clone(&self.metrics),
         )))
     }
 
@@ -51,6 +56,31 @@ impl RootContext for FilterRoot {
         match serde_json::from_slice::<PluginConfiguration>(&configuration) {
             Ok(config) => {
                 info!("plugin config parsed: {:?}", config);
+                let summary = ConfigSummary::from(&config);
+                let decision_header = config.decision_header.clone();
+                let expose_errors = config.expose_errors;
+                let explain = config.explain;
+                let request_deadline = config.request_deadline.as_ref().map(|timeout| timeout.0);
+                let grpc_duration_metrics = config.grpc_duration_metrics;
+                let selection_mode = config.selection_mode;
+                let attributes = AttributeSettings {
+                    trust_forwarded_proto: config.trust_forwarded_proto,
+                    config_generation: self.config_generation + 1,
+                    geo_country_source: config.geo.country.as_deref().map(Into::into),
+                    geo_asn_source: config.geo.asn.as_deref().map(Into::into),
+                };
+                let trace_when = match config
+                    .tracing
+                    .as_ref()
+                    .map(|tracing| Predicate::new(&tracing.when))
+                    .transpose()
+                {
+                    Ok(predicate) => predicate.map(Rc::new),
+                    Err(err) => {
+                        error!("failed to compile tracing predicate: {}", err);
+                        return false;
+                    }
+                };
                 let action_set_index =
                     match <PluginConfiguration as TryInto<ActionSetIndex>>::try_into(config) {
                         Ok(cfg) => cfg,
@@ -59,7 +89,20 @@ impl RootContext for FilterRoot {
                             return false;
                         }
                     };
-                self.action_set_index = Rc::new(action_set_index);
+                info!("plugin config compiled: {summary}");
+                self.config_generation = attributes.config_generation;
+                self.config = Rc::new(FilterConfig {
+                    index: action_set_index,
+                    decision_header,
+                    expose_errors,
+                    explain,
+                    trace_when,
+                    request_deadline,
+                    grpc_duration_metrics,
+                    selection_mode,
+                    attributes: Rc::new(attributes),
+                });
+                self.metrics.increment(CONFIG_RELOADS_METRIC, &[]);
             }
             Err(e) => {
                 error!("failed to parse plugin config: {}", e);
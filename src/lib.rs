@@ -1,11 +1,15 @@
 mod action_set_index;
 mod auth_action;
 mod configuration;
+mod connection_counter;
 mod data;
+mod decision_cache;
 #[allow(renamed_and_removed_lints)]
 mod envoy;
 mod filter;
 mod glob;
+mod lru;
+mod metrics;
 mod ratelimit_action;
 mod runtime_action;
 mod runtime_action_set;
@@ -31,9 +35,11 @@ mod service;
 // This is a C interface, so make it explicit in the fn signature (and avoid mangling)
 extern "C" fn start() {
     use crate::filter::root_context::FilterRoot;
+    use crate::metrics::{HostMetricsSink, Metrics};
     use log::info;
     use proxy_wasm::traits::RootContext;
     use proxy_wasm::types::LogLevel;
+    use std::rc::Rc;
 
     proxy_wasm::set_log_level(LogLevel::Trace);
     std::panic::set_hook(Box::new(|panic_info| {
@@ -44,7 +50,9 @@ extern "C" fn start() {
         info!("#{} set_root_context", context_id);
         Box::new(FilterRoot {
             context_id,
-            action_set_index: Default::default(),
+            config: Default::default(),
+            metrics: Rc::new(Metrics::new(Rc::new(HostMetricsSink))),
+            config_generation: 0,
         })
     });
 }
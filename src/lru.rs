@@ -0,0 +1,122 @@
+// A map bounded to a capacity, evicting the least recently used entry to make room for a new one.
+//
+// Entries are ordered by when they were last used, so that both lookups and evictions stay
+// logarithmic in the capacity.
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug)]
+pub struct Lru<K, V> {
+    capacity: usize,
+    // key => (value, when last used)
+    entries: HashMap<K, (V, u64)>,
+    // when last used => key
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::default(),
+            recency: BTreeMap::default(),
+            clock: 0,
+        }
+    }
+
+    /// The value of the key, marked as the most recently used
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.clock += 1;
+        let (value, last_used) = self.entries.get_mut(key)?;
+        if let Some(key) = self.recency.remove(&*last_used) {
+            self.recency.insert(self.clock, key);
+        }
+        *last_used = self.clock;
+        Some(value)
+    }
+
+    /// Inserts the value, evicting the least recently used entry if at capacity; nothing is kept
+    /// without any capacity
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if let Some((_, last_used)) = self.entries.get(&key) {
+            self.recency.remove(last_used);
+        } else if self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (value, self.clock));
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, last_used) = self.entries.remove(key)?;
+        self.recency.remove(&last_used);
+        Some(value)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lru;
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        assert_eq!(lru.get_mut("a"), Some(&mut 1));
+
+        // b is the least recently used
+        lru.insert("c", 3);
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.get_mut("b"), None);
+        assert_eq!(lru.get_mut("a"), Some(&mut 1));
+        assert_eq!(lru.get_mut("c"), Some(&mut 3));
+    }
+
+    #[test]
+    fn reinserting_a_key_replaces_its_value() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        lru.insert("a", 3);
+        assert_eq!(lru.len(), 2);
+
+        // a was used since, b is the least recently used
+        lru.insert("c", 4);
+        assert_eq!(lru.get_mut("b"), None);
+        assert_eq!(lru.get_mut("a"), Some(&mut 3));
+    }
+
+    #[test]
+    fn keeps_nothing_without_capacity() {
+        let mut lru = Lru::new(0);
+        lru.insert("a", 1);
+        assert_eq!(lru.len(), 0);
+        assert_eq!(lru.remove("a"), None);
+    }
+}
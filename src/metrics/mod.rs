@@ -0,0 +1,232 @@
+mod sink;
+
+use log::warn;
+use proxy_wasm::types::MetricType;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+pub use sink::{HostMetricsSink, MetricsSink};
+
+/// A counter, as defined in a sink
+pub struct Counter {
+    id: u32,
+    sink: Rc<dyn MetricsSink>,
+}
+
+impl Counter {
+    pub fn new(sink: Rc<dyn MetricsSink>, name: &str) -> Option<Self> {
+        match sink.define(MetricType::Counter, name) {
+            Ok(id) => Some(Self { id, sink }),
+            Err(status) => {
+                warn!("failed to define metric {name}: {status:?}");
+                None
+            }
+        }
+    }
+
+    pub fn increment(&self) {
+        if let Err(status) = self.sink.increment(self.id, 1) {
+            warn!("failed to increment metric {}: {status:?}", self.id);
+        }
+    }
+}
+
+/// A histogram, as defined in a sink
+pub struct Histogram {
+    id: u32,
+    sink: Rc<dyn MetricsSink>,
+}
+
+impl Histogram {
+    pub fn new(sink: Rc<dyn MetricsSink>, name: &str) -> Option<Self> {
+        match sink.define(MetricType::Histogram, name) {
+            Ok(id) => Some(Self { id, sink }),
+            Err(status) => {
+                warn!("failed to define metric {name}: {status:?}");
+                None
+            }
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        if let Err(status) = self.sink.record(self.id, value) {
+            warn!("failed to record metric {}: {status:?}", self.id);
+        }
+    }
+}
+
+/// The labeled counters and histograms, each defined once in the sink when first used
+pub struct Metrics {
+    sink: Rc<dyn MetricsSink>,
+    counters: RefCell<HashMap<String, Rc<Counter>>>,
+    histograms: RefCell<HashMap<String, Rc<Histogram>>>,
+}
+
+impl Metrics {
+    pub fn new(sink: Rc<dyn MetricsSink>) -> Self {
+        Self {
+            sink,
+            counters: RefCell::default(),
+            histograms: RefCell::default(),
+        }
+    }
+
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        if let Some(counter) = self.counter(&labeled_name(name, labels)) {
+            counter.increment();
+        }
+    }
+
+    pub fn record(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        if let Some(histogram) = self.histogram(&labeled_name(name, labels)) {
+            histogram.record(value);
+        }
+    }
+
+    fn counter(&self, name: &str) -> Option<Rc<Counter>> {
+        if let Some(counter) = self.counters.borrow().get(name) {
+            return Some(Rc::clone(counter));
+        }
+        let counter = Rc::new(Counter::new(Rc::clone(&self.sink), name)?);
+        self.counters
+            .borrow_mut()
+            .insert(name.to_string(), Rc::clone(&counter));
+        Some(counter)
+    }
+
+    fn histogram(&self, name: &str) -> Option<Rc<Histogram>> {
+        if let Some(histogram) = self.histograms.borrow().get(name) {
+            return Some(Rc::clone(histogram));
+        }
+        let histogram = Rc::new(Histogram::new(Rc::clone(&self.sink), name)?);
+        self.histograms
+            .borrow_mut()
+            .insert(name.to_string(), Rc::clone(&histogram));
+        Some(histogram)
+    }
+}
+
+/// Name of a metric carrying labels, as proxy-wasm metrics have names but no labels, e.g.
+/// `kuadrant.denied.action_set_X.domain_Y`. Each label is appended as a `.{key}_{value}`
+/// segment, in the order given, so that a same label set always yields a same name.
+pub fn labeled_name(name: &str, labels: &[(&str, &str)]) -> String {
+    let mut labeled = String::from(name);
+    for (key, value) in labels {
+        labeled.push('.');
+        escape_into(&mut labeled, key);
+        labeled.push('_');
+        escape_into(&mut labeled, value);
+    }
+    labeled
+}
+
+// Only keeps the characters valid in Prometheus names. Any other is replaced with `_` followed
+// by its UTF-8 bytes in hex, and `_` itself is doubled, so that distinct values never collide.
+fn escape_into(out: &mut String, raw: &str) {
+    for c in raw.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => out.push(c),
+            '_' => out.push_str("__"),
+            _ => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    let _ = write!(out, "_{byte:02x}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sink::test::InMemoryMetricsSink;
+    use super::{labeled_name, Counter, Metrics};
+    use std::rc::Rc;
+
+    #[test]
+    fn counters_are_incremented_in_the_sink() {
+        let sink = Rc::new(InMemoryMetricsSink::default());
+        let counter = Counter::new(sink.clone(), "kuadrant.configs").expect("defined in memory");
+        assert_eq!(sink.value("kuadrant.configs"), Some(0));
+
+        counter.increment();
+        counter.increment();
+        assert_eq!(sink.value("kuadrant.configs"), Some(2));
+    }
+
+    #[test]
+    fn labeled_counters_are_defined_once() {
+        let sink = Rc::new(InMemoryMetricsSink::default());
+        let metrics = Metrics::new(sink.clone());
+
+        metrics.increment("kuadrant.denied", &[("action_set", "a")]);
+        metrics.increment("kuadrant.denied", &[("action_set", "b")]);
+        metrics.increment("kuadrant.denied", &[("action_set", "a")]);
+
+        assert_eq!(sink.value("kuadrant.denied.action__set_a"), Some(2));
+        assert_eq!(sink.value("kuadrant.denied.action__set_b"), Some(1));
+        assert_eq!(sink.value("kuadrant.denied"), None);
+    }
+
+    #[test]
+    fn labeled_histograms_record_in_the_sink() {
+        let sink = Rc::new(InMemoryMetricsSink::default());
+        let metrics = Metrics::new(sink.clone());
+
+        metrics.record("kuadrant.grpc_duration_ms", &[("service", "auth")], 12);
+        metrics.record("kuadrant.grpc_duration_ms", &[("service", "ratelimit")], 3);
+        metrics.record("kuadrant.grpc_duration_ms", &[("service", "auth")], 7);
+
+        assert_eq!(
+            sink.recorded("kuadrant.grpc_duration_ms.service_auth"),
+            vec![12, 7]
+        );
+        assert_eq!(
+            sink.recorded("kuadrant.grpc_duration_ms.service_ratelimit"),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn labels_are_appended_in_order() {
+        assert_eq!(labeled_name("kuadrant.denied", &[]), "kuadrant.denied");
+        assert_eq!(
+            labeled_name(
+                "kuadrant.denied",
+                &[("action_set", "toystore"), ("domain", "limited")]
+            ),
+            "kuadrant.denied.action__set_toystore.domain_limited"
+        );
+    }
+
+    #[test]
+    fn invalid_characters_are_sanitized() {
+        assert_eq!(
+            labeled_name("kuadrant.denied", &[("domain", "ns-a/rlp.a")]),
+            "kuadrant.denied.domain_ns_2da_2frlp_2ea"
+        );
+        assert_eq!(
+            labeled_name("kuadrant.denied", &[("tenant", "ü")]),
+            "kuadrant.denied.tenant__c3_bc"
+        );
+    }
+
+    #[test]
+    fn sanitized_labels_do_not_collide() {
+        let names = [
+            labeled_name("m", &[("domain", "a-b")]),
+            labeled_name("m", &[("domain", "a_b")]),
+            labeled_name("m", &[("domain", "a.b")]),
+            labeled_name("m", &[("domain_a", "b")]),
+            labeled_name("m", &[("domain", "a"), ("b", "")]),
+        ];
+        for (i, name) in names.iter().enumerate() {
+            assert!(
+                names[i + 1..].iter().all(|other| other != name),
+                "{name} collides"
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use proxy_wasm::types::{MetricType, Status};
+
+/// Where the counters and histograms are kept, the host by default
+pub trait MetricsSink {
+    fn define(&self, metric_type: MetricType, name: &str) -> Result<u32, Status>;
+    fn increment(&self, id: u32, offset: i64) -> Result<(), Status>;
+    fn record(&self, id: u32, value: u64) -> Result<(), Status>;
+}
+
+/// The proxy-wasm host's metrics, e.g. Envoy stats
+pub struct HostMetricsSink;
+
+impl MetricsSink for HostMetricsSink {
+    fn define(&self, metric_type: MetricType, name: &str) -> Result<u32, Status> {
+        proxy_wasm::hostcalls::define_metric(metric_type, name)
+    }
+
+    fn increment(&self, id: u32, offset: i64) -> Result<(), Status> {
+        proxy_wasm::hostcalls::increment_metric(id, offset)
+    }
+
+    fn record(&self, id: u32, value: u64) -> Result<(), Status> {
+        proxy_wasm::hostcalls::record_metric(id, value)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::MetricsSink;
+    use proxy_wasm::types::{MetricType, Status};
+    use std::cell::RefCell;
+
+    /// Keeps the counters, and the values recorded in histograms, in memory, for them to be
+    /// asserted on
+    #[derive(Default)]
+    pub struct InMemoryMetricsSink {
+        counters: RefCell<Vec<(String, i64)>>,
+        recorded: RefCell<Vec<(u32, u64)>>,
+    }
+
+    impl InMemoryMetricsSink {
+        pub fn value(&self, name: &str) -> Option<i64> {
+            self.counters
+                .borrow()
+                .iter()
+                .find(|(counter, _)| counter == name)
+                .map(|(_, value)| *value)
+        }
+
+        pub fn recorded(&self, name: &str) -> Vec<u64> {
+            let Some(id) = self
+                .counters
+                .borrow()
+                .iter()
+                .position(|(metric, _)| metric == name)
+            else {
+                return Vec::default();
+            };
+            self.recorded
+                .borrow()
+                .iter()
+                .filter(|(metric, _)| *metric as usize == id)
+                .map(|(_, value)| *value)
+                .collect()
+        }
+    }
+
+    impl MetricsSink for InMemoryMetricsSink {
+        fn define(&self, _metric_type: MetricType, name: &str) -> Result<u32, Status> {
+            let mut counters = self.counters.borrow_mut();
+            match counters.iter().position(|(counter, _)| counter == name) {
+                Some(id) => Ok(id as u32),
+                None => {
+                    counters.push((name.to_string(), 0));
+                    Ok((counters.len() - 1) as u32)
+                }
+            }
+        }
+
+        fn increment(&self, id: u32, offset: i64) -> Result<(), Status> {
+            match self.counters.borrow_mut().get_mut(id as usize) {
+                Some((_, value)) => {
+                    *value += offset;
+                    Ok(())
+                }
+                None => Err(Status::NotFound),
+            }
+        }
+
+        fn record(&self, id: u32, value: u64) -> Result<(), Status> {
+            if id as usize >= self.counters.borrow().len() {
+                return Err(Status::NotFound);
+            }
+            self.recorded.borrow_mut().push((id, value));
+            Ok(())
+        }
+    }
+}
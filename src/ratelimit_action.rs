@@ -1,15 +1,22 @@
-use crate::configuration::{Action, DataType, FailureMode, Service};
+use crate::configuration::{
+    Action, DataType, EnforcementMode, FailureMode, HeaderFilter, ResetFormat, Service, TenantScope,
+};
+use crate::data::get_attribute;
 use crate::data::Expression;
-use crate::data::Predicate;
+use crate::data::{Predicate, PredicateVec};
 use crate::envoy::{
     HeaderValue, RateLimitDescriptor, RateLimitDescriptor_Entry, RateLimitResponse,
     RateLimitResponse_Code, StatusCode,
 };
-use crate::service::{GrpcErrResponse, GrpcService, Headers};
+use crate::service::{DenyReply, GrpcErrResponse, GrpcService, Headers};
 use cel_interpreter::Value;
-use log::{debug, error};
-use protobuf::RepeatedField;
+use chrono::{DateTime, FixedOffset};
+use log::{debug, error, warn};
+use protobuf::{Message, RepeatedField};
+use regex::Regex;
+use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::SystemTime;
 
 #[derive(Debug)]
 struct DescriptorEntryBuilder {
@@ -43,6 +50,7 @@ impl DescriptorEntryBuilder {
                     // todo this probably should be a proper string literal!
                     Value::String(s) => (*s).clone(),
                     Value::Bool(b) => format!("{b}"),
+                    Value::Bytes(b) => String::from_utf8_lossy(&b).into_owned(),
                     Value::Null => "null".to_owned(),
                     _ => panic!("Only scalar values can be sent as data"),
                 },
@@ -105,20 +113,153 @@ impl ConditionalData {
     }
 }
 
+#[derive(Debug)]
+struct TenantScopeBuilder {
+    config: TenantScope,
+    expression: Expression,
+    allowed: Regex,
+}
+
+impl TenantScopeBuilder {
+    pub fn new(config: &TenantScope) -> Result<Self, String> {
+        Ok(Self {
+            config: config.clone(),
+            expression: Expression::new(&config.expression).map_err(|e| e.to_string())?,
+            allowed: Regex::new(&format!("^(?:{})$", config.allowed_pattern))
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    pub fn evaluate(&self) -> Result<String, String> {
+        match self.expression.eval()? {
+            Value::String(tenant) if self.allowed.is_match(&tenant) => Ok((*tenant).clone()),
+            value => Err(format!(
+                "tenant {value:?} does not match `{}`",
+                self.config.allowed_pattern
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ScopeBuilder {
+    source: String,
+    expression: Expression,
+}
+
+impl ScopeBuilder {
+    pub fn new(source: &str) -> Result<Self, String> {
+        Ok(Self {
+            source: source.to_string(),
+            expression: Expression::new(source).map_err(|e| e.to_string())?,
+        })
+    }
+
+    pub fn evaluate(&self) -> Result<String, String> {
+        match self.expression.eval()? {
+            Value::String(scope) if !scope.is_empty() => Ok((*scope).clone()),
+            value => Err(format!("scope {value:?} is not a non-empty string")),
+        }
+    }
+}
+
+// Response predicate of the actions awaiting the response trailers
+const RESPONSE_TRAILERS_RECEIVED: &str = "size(response.trailers) > 0";
+
+// Prefixes of the rate limiting headers, the legacy and the IETF draft ones
+const RATE_LIMIT_HEADER_PREFIXES: [&str; 2] = ["x-ratelimit-", "ratelimit-"];
+
+fn is_reset_header(header: &str) -> bool {
+    RATE_LIMIT_HEADER_PREFIXES.iter().any(|known| {
+        header
+            .get(..known.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(known))
+            && header[known.len()..].eq_ignore_ascii_case("reset")
+    })
+}
+
+// When the request was received, in seconds since the epoch
+fn request_received_at() -> Option<i64> {
+    match get_attribute::<DateTime<FixedOffset>>(&"request.time".into()) {
+        Ok(received_at) => received_at.map(|received_at| received_at.timestamp()),
+        Err(e) => {
+            debug!("request_received_at: {e}");
+            None
+        }
+    }
+}
+
+// Safe methods only read, any other method is accounted for as a write
+fn access_suffix(method: &str) -> &'static str {
+    match method {
+        "GET" | "HEAD" | "OPTIONS" | "TRACE" => ":read",
+        _ => ":write",
+    }
+}
+
 #[derive(Debug)]
 pub struct RateLimitAction {
     grpc_service: Rc<GrpcService>,
     scope: String,
+    scope_expression: Option<ScopeBuilder>,
     service_name: String,
+    tenant_scope: Option<TenantScopeBuilder>,
+    read_write_domains: bool,
+    response_predicates: Vec<Predicate>,
+    dry_run: bool,
+    response_headers: Option<HeaderFilter>,
+    rate_limit_headers_prefix: Option<String>,
+    on_response_body: bool,
+    on_response_trailers: bool,
+    reset_format: ResetFormat,
+    deny_response: Option<DenyReply>,
+    decision_cache: Option<(DecisionCacheConfig, RefCell<DecisionCache>)>,
     conditional_data_sets: Vec<ConditionalData>,
 }
 
 impl RateLimitAction {
     pub fn new(action: &Action, service: &Service) -> Result<Self, String> {
+        let mut response_predicates = Vec::default();
+        if action.on_response_trailers {
+            // the responses without trailers end with their body, the action doesn't count them
+            response_predicates
+                .push(Predicate::new(RESPONSE_TRAILERS_RECEIVED).map_err(|e| e.to_string())?);
+        }
+        for predicate in &action.response_predicates {
+            response_predicates.push(Predicate::new(predicate).map_err(|e| e.to_string())?);
+        }
         Ok(Self {
             grpc_service: Rc::new(GrpcService::new(Rc::new(service.clone()))),
             scope: action.scope.clone(),
+            scope_expression: action
+                .scope_expression
+                .as_deref()
+                .map(ScopeBuilder::new)
+                .transpose()?,
             service_name: action.service.clone(),
+            tenant_scope: action
+                .tenant_scope
+                .as_ref()
+                .map(TenantScopeBuilder::new)
+                .transpose()?,
+            read_write_domains: action.read_write_domains,
+            response_predicates,
+            dry_run: action.enforcement_mode == EnforcementMode::DryRun,
+            response_headers: action.response_headers.clone(),
+            rate_limit_headers_prefix: action.rate_limit_headers_prefix.clone(),
+            on_response_body: action.on_response_body,
+            on_response_trailers: action.on_response_trailers,
+            reset_format: action.rate_limit_reset_format,
+            deny_response: action
+                .deny_response
+                .as_ref()
+                .map(DenyReply::new)
+                .transpose()?,
+            decision_cache: action.decision_cache.as_ref().map(|config| {
+                let capacity = config.capacity.unwrap_or(decision_cache::DEFAULT_CAPACITY);
+                let cache = DecisionCache::new(config.ttl.0, capacity);
+                (config.clone(), RefCell::new(cache))
+            }),
             conditional_data_sets: vec![ConditionalData::new(action)?],
         })
     }
@@ -143,6 +284,44 @@ impl RateLimitAction {
         self.scope.as_str()
     }
 
+    /// The rate limiting domain, i.e. the scope, or the one resolved for the request when set
+    /// as an expression, suffixed with the tenant when scoped to one. A tenant not matching the
+    /// allowed pattern is denied, so a forged one can't escape its own limits. When opted in,
+    /// the domain is further suffixed with the kind of access of the request.
+    pub fn domain(&self) -> Result<String, GrpcErrResponse> {
+        let scope = match &self.scope_expression {
+            None => self.scope.clone(),
+            Some(scope_expression) => scope_expression.evaluate().map_err(|e| {
+                warn!("domain(rl): failed to resolve scope {}: {e}", self.scope);
+                GrpcErrResponse::new_internal_server_error()
+            })?,
+        };
+        let mut domain = match &self.tenant_scope {
+            None => scope,
+            Some(tenant_scope) => match tenant_scope.evaluate() {
+                Ok(tenant) => format!("{scope}/{tenant}"),
+                Err(e) => {
+                    warn!("domain(rl): invalid tenant for scope {}: {e}", self.scope);
+                    return Err(GrpcErrResponse::new(
+                        StatusCode::Forbidden as u32,
+                        Vec::default(),
+                        "Forbidden\n".to_string(),
+                    ));
+                }
+            },
+        };
+        if self.read_write_domains {
+            let method = get_attribute::<String>(&"request.method".into())
+                .unwrap_or_else(|e| {
+                    warn!("domain(rl): failed to retrieve request.method: {e}");
+                    None
+                })
+                .unwrap_or_default();
+            domain.push_str(access_suffix(&method));
+        }
+        Ok(domain)
+    }
+
     pub fn conditions_apply(&self) -> bool {
         // For RateLimitAction conditions always apply.
         // It is when building the descriptor that it may be empty because predicates do not
@@ -150,13 +329,57 @@ impl RateLimitAction {
         true
     }
 
+    /// Whether the action only runs on the response, i.e. has response predicates or awaits
+    /// the response body or trailers
+    pub fn is_deferred(&self) -> bool {
+        !self.response_predicates.is_empty() || self.awaits_response_body()
+    }
+
+    /// Whether the action only runs at the end of the response body, i.e. its last chunk or
+    /// its trailers
+    pub fn awaits_response_body(&self) -> bool {
+        self.on_response_body || self.on_response_trailers
+    }
+
+    /// Whether the requests over the limit are let through, only counted as shadow denials
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn response_conditions_apply(&self) -> bool {
+        self.response_predicates.apply()
+    }
+
     pub fn get_failure_mode(&self) -> FailureMode {
         self.grpc_service.get_failure_mode()
     }
 
     #[must_use]
     pub fn merge(&mut self, other: RateLimitAction) -> Option<RateLimitAction> {
-        if self.scope == other.scope && self.service_name == other.service_name {
+        if self.scope == other.scope
+            && self.scope_expression.as_ref().map(|s| &s.source)
+                == other.scope_expression.as_ref().map(|s| &s.source)
+            && self.service_name == other.service_name
+            && self.tenant_scope.as_ref().map(|t| &t.config)
+                == other.tenant_scope.as_ref().map(|t| &t.config)
+            && self.read_write_domains == other.read_write_domains
+            && self.dry_run == other.dry_run
+            && self.response_headers == other.response_headers
+            && self.rate_limit_headers_prefix == other.rate_limit_headers_prefix
+            && self.on_response_body == other.on_response_body
+            && self.on_response_trailers == other.on_response_trailers
+            && self.reset_format == other.reset_format
+            && self.deny_response == other.deny_response
+            && self.decision_cache.as_ref().map(|(config, _)| config)
+                == other.decision_cache.as_ref().map(|(config, _)| config)
+            && self.get_failure_mode() == other.get_failure_mode()
+            && self.grpc_service.get_timeout() == other.grpc_service.get_timeout()
+            && self
+                .response_predicates
+                .iter()
+                .map(Predicate::source)
+                .eq(other.response_predicates.iter().map(Predicate::source))
+        {
             self.conditional_data_sets
                 .extend(other.conditional_data_sets);
             return None;
@@ -164,6 +387,30 @@ impl RateLimitAction {
         Some(other)
     }
 
+    pub fn caches_decisions(&self) -> bool {
+        self.decision_cache.is_some()
+    }
+
+    /// The response to an identical request found over the limit, if still cached
+    pub fn cached_response(&self, request: &[u8], now: SystemTime) -> Option<Vec<u8>> {
+        let (_, cache) = self.decision_cache.as_ref()?;
+        cache.borrow_mut().get(request, now)
+    }
+
+    /// Keeps the response to the request, if over the limit and decisions are cached
+    pub fn cache_response(&self, request: &[u8], response: &[u8], now: SystemTime) {
+        let Some((_, cache)) = &self.decision_cache else {
+            return;
+        };
+        let over_limit = RateLimitResponse::parse_from_bytes(response)
+            .is_ok_and(|parsed| parsed.overall_code == RateLimitResponse_Code::OVER_LIMIT);
+        if over_limit {
+            cache
+                .borrow_mut()
+                .insert(request.to_vec(), response.to_vec(), now);
+        }
+    }
+
     pub fn process_response(
         &self,
         rate_limit_response: RateLimitResponse,
@@ -180,6 +427,10 @@ impl RateLimitAction {
                         debug!("process_response(rl): continuing as FailureMode Allow");
                         Ok(Vec::default())
                     }
+                    FailureMode::Passthrough => {
+                        debug!("process_response(rl): continuing as FailureMode Passthrough");
+                        Ok(self.grpc_service.degraded_headers())
+                    }
                 }
             }
             RateLimitResponse {
@@ -188,12 +439,17 @@ impl RateLimitAction {
                 ..
             } => {
                 debug!("process_response(rl): received OVER_LIMIT response");
-                let response_headers = Self::get_header_vec(rl_headers);
-                Err(GrpcErrResponse::new(
-                    StatusCode::TooManyRequests as u32,
-                    response_headers,
-                    "Too Many Requests\n".to_string(),
-                ))
+                let response_headers = self.get_header_vec(rl_headers);
+                match &self.deny_response {
+                    Some(deny_response) => {
+                        Err(GrpcErrResponse::new_denied(deny_response, response_headers))
+                    }
+                    None => Err(GrpcErrResponse::new(
+                        StatusCode::TooManyRequests as u32,
+                        response_headers,
+                        "Too Many Requests\n".to_string(),
+                    )),
+                }
             }
             RateLimitResponse {
                 overall_code: RateLimitResponse_Code::OK,
@@ -201,26 +457,80 @@ impl RateLimitAction {
                 ..
             } => {
                 debug!("process_response(rl): received OK response");
-                Ok(Self::get_header_vec(additional_headers))
+                Ok(self.get_header_vec(additional_headers))
             }
         }
     }
 
-    fn get_header_vec(headers: RepeatedField<HeaderValue>) -> Headers {
+    // The headers to add to the response, only those let through when filtered, renamed with
+    // the configured prefix if any
+    fn get_header_vec(&self, headers: RepeatedField<HeaderValue>) -> Headers {
+        self.get_header_vec_received_at(headers, request_received_at)
+    }
+
+    fn get_header_vec_received_at(
+        &self,
+        headers: RepeatedField<HeaderValue>,
+        received_at: impl Fn() -> Option<i64>,
+    ) -> Headers {
         headers
             .iter()
-            .map(|header| (header.key.to_owned(), header.value.to_owned()))
+            .filter(|header| match &self.response_headers {
+                None => true,
+                Some(filter) => filter.lets_through(&header.key),
+            })
+            .map(|header| {
+                (
+                    self.header_name(&header.key),
+                    self.header_value(header, &received_at),
+                )
+            })
             .collect()
     }
+
+    fn header_name(&self, header: &str) -> String {
+        let Some(prefix) = &self.rate_limit_headers_prefix else {
+            return header.to_owned();
+        };
+        RATE_LIMIT_HEADER_PREFIXES
+            .iter()
+            .find_map(|known| {
+                header
+                    .get(..known.len())
+                    .filter(|start| start.eq_ignore_ascii_case(known))
+                    .map(|_| format!("{prefix}{}", &header[known.len()..]))
+            })
+            .unwrap_or_else(|| header.to_owned())
+    }
+
+    // The seconds until the reset are told as when it happens, relative to when the request
+    // was received, if so configured
+    fn header_value(&self, header: &HeaderValue, received_at: impl Fn() -> Option<i64>) -> String {
+        if self.reset_format == ResetFormat::Epoch && is_reset_header(&header.key) {
+            if let Some(reset) = header
+                .value
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .zip(received_at())
+                .map(|(delta, received_at)| received_at.saturating_add(delta))
+            {
+                return reset.to_string();
+            }
+            debug!("header_value: leaving `{}` as it is", header.key);
+        }
+        header.value.to_owned()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::configuration::{
-        Action, DataItem, DataType, ExpressionItem, FailureMode, Service, ServiceType, StaticItem,
-        Timeout,
+        Action, DataItem, DataType, ExpressionItem, FailureMode, ResetFormat, Service, ServiceType,
+        StaticItem, Timeout,
     };
+    use std::time::Duration;
 
     fn build_service() -> Service {
         build_service_with_failure_mode(FailureMode::default())
@@ -230,8 +540,13 @@ mod test {
         Service {
             service_type: ServiceType::RateLimit,
             endpoint: "some_endpoint".into(),
-            failure_mode,
+            failure_mode: Some(failure_mode),
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         }
     }
 
@@ -239,8 +554,22 @@ mod test {
         Action {
             service: "some_service".into(),
             scope: "some_scope".into(),
+            scope_expression: None,
             predicates,
             data,
+            tenant_scope: None,
+            read_write_domains: false,
+            response_predicates: Vec::default(),
+            enforcement_mode: EnforcementMode::default(),
+            response_headers: None,
+            rate_limit_headers_prefix: None,
+            on_response_body: false,
+            on_response_trailers: false,
+            failure_mode: None,
+            timeout: None,
+            rate_limit_reset_format: ResetFormat::default(),
+            deny_response: None,
+            decision_cache: None,
         }
     }
 
@@ -318,6 +647,45 @@ mod test {
         assert_eq!(descriptor.get_entries()[0].value, String::from("value_1"));
     }
 
+    #[test]
+    fn descriptor_entry_from_bytes() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "user".into(),
+                value: "base64Decode('YWxpY2U', 'url')".into(),
+            }),
+        }];
+        let action = build_action(Vec::default(), data);
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let descriptor = rl_action.build_descriptor();
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("user"));
+        assert_eq!(descriptor.get_entries()[0].value, String::from("alice"));
+    }
+
+    #[test]
+    fn descriptor_entry_from_sha256() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "user".into(),
+                value: "sha256('alice')".into(),
+            }),
+        }];
+        let action = build_action(Vec::default(), data);
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let descriptor = rl_action.build_descriptor();
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("user"));
+        assert_eq!(
+            descriptor.get_entries()[0].value,
+            String::from("2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90")
+        );
+    }
+
     #[test]
     fn descriptor_entry_from_static() {
         let data = vec![DataItem {
@@ -336,6 +704,131 @@ mod test {
         assert_eq!(descriptor.get_entries()[0].value, String::from("value_1"));
     }
 
+    #[test]
+    fn descriptor_entries_from_non_string_expressions() {
+        let data = [
+            ("tokens", "40 + 2"),
+            ("unsigned", "42u"),
+            ("ratio", "0.5"),
+            ("streaming", "1 > 0"),
+        ]
+        .into_iter()
+        .map(|(key, value)| DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: key.into(),
+                value: value.into(),
+            }),
+        })
+        .collect();
+        let action = build_action(Vec::default(), data);
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let descriptor = rl_action.build_descriptor();
+        let entries: Vec<(&str, &str)> = descriptor
+            .get_entries()
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("tokens", "42"),
+                ("unsigned", "42"),
+                ("ratio", "0.5"),
+                ("streaming", "true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn descriptor_entry_from_response_trailer() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "tokens".into(),
+                value: "responseTrailer('x-usage-tokens')".into(),
+            }),
+        }];
+        let action = build_action(Vec::default(), data);
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let descriptor = rl_action.build_descriptor();
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("tokens"));
+        assert_eq!(descriptor.get_entries()[0].value, String::from("42"));
+    }
+
+    #[test]
+    fn descriptor_entry_from_response_body_size() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "bytes".into(),
+                value: "response.body_size".into(),
+            }),
+        }];
+        let mut action = build_action(Vec::default(), data);
+        action.on_response_body = true;
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert!(rl_action.is_deferred());
+        assert!(rl_action.awaits_response_body());
+
+        // as the filter sets it once the last chunk went through, e.g. of 1024 + 1024 + 512
+        crate::data::response_body_size(Some(2560));
+        let descriptor = rl_action.build_descriptor();
+        crate::data::response_body_size(None);
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("bytes"));
+        assert_eq!(descriptor.get_entries()[0].value, String::from("2560"));
+    }
+
+    #[test]
+    fn awaits_the_response_trailers() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "tokens".into(),
+                value: "responseTrailer('x-usage-tokens')".into(),
+            }),
+        }];
+        let mut action = build_action(Vec::default(), data);
+        action.on_response_trailers = true;
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert!(rl_action.is_deferred());
+        assert!(rl_action.awaits_response_body());
+
+        // as per the response trailers received
+        assert!(rl_action.response_conditions_apply());
+        let descriptor = rl_action.build_descriptor();
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("tokens"));
+        assert_eq!(descriptor.get_entries()[0].value, String::from("42"));
+    }
+
+    #[test]
+    fn descriptor_entry_from_format() {
+        let data = vec![DataItem {
+            item: DataType::Expression(ExpressionItem {
+                key: "key".into(),
+                value: "format('tenant:%s:route:%s:version:%d', 'acme', '/toys', 2)".into(),
+            }),
+        }];
+        let action = build_action(Vec::default(), data);
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let descriptor = rl_action.build_descriptor();
+        assert_eq!(descriptor.get_entries().len(), 1);
+        assert_eq!(descriptor.get_entries()[0].key, String::from("key"));
+        assert_eq!(
+            descriptor.get_entries()[0].value,
+            String::from("tenant:acme:route:/toys:version:2")
+        );
+    }
+
     #[test]
     fn descriptor_entries_not_generated_when_predicates_evaluate_to_false() {
         let data = vec![DataItem {
@@ -477,6 +970,259 @@ mod test {
         assert_eq!(grpc_err_response.body(), "Too Many Requests\n");
     }
 
+    #[test]
+    fn only_the_allowed_response_headers_are_added() {
+        let headers = vec![("x-ratelimit-limit", "10"), ("X-RateLimit-Remaining", "0")];
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.response_headers = Some(HeaderFilter::Allow(vec!["x-ratelimit-remaining".into()]));
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let ok_response =
+            build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers.clone()));
+        assert_eq!(
+            rl_action.process_response(ok_response).expect("is ok"),
+            vec![("X-RateLimit-Remaining".to_string(), "0".to_string())]
+        );
+
+        let overlimit_response =
+            build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, Some(headers));
+        let grpc_err_response = rl_action
+            .process_response(overlimit_response)
+            .expect_err("is err");
+        assert_eq!(
+            grpc_err_response.headers(),
+            vec![("X-RateLimit-Remaining", "0")]
+        );
+    }
+
+    #[test]
+    fn rate_limit_headers_are_renamed_with_the_prefix() {
+        let headers = vec![
+            ("X-RateLimit-Limit", "10"),
+            ("ratelimit-remaining", "0"),
+            ("retry-after", "60"),
+        ];
+        let mut action = build_action(Vec::default(), Vec::default());
+
+        action.rate_limit_headers_prefix = Some("RateLimit-".into());
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        let ok_response =
+            build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers.clone()));
+        assert_eq!(
+            rl_action.process_response(ok_response).expect("is ok"),
+            vec![
+                ("RateLimit-Limit".to_string(), "10".to_string()),
+                ("RateLimit-remaining".to_string(), "0".to_string()),
+                ("retry-after".to_string(), "60".to_string()),
+            ]
+        );
+
+        action.rate_limit_headers_prefix = Some("X-RateLimit-".into());
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        let overlimit_response =
+            build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, Some(headers));
+        let grpc_err_response = rl_action
+            .process_response(overlimit_response)
+            .expect_err("is err");
+        assert_eq!(
+            grpc_err_response.headers(),
+            vec![
+                ("X-RateLimit-Limit", "10"),
+                ("X-RateLimit-remaining", "0"),
+                ("retry-after", "60"),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_response_headers_are_added_when_none_is_allowed() {
+        let headers = vec![("x-ratelimit-limit", "10"), ("x-ratelimit-remaining", "0")];
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.response_headers = Some(HeaderFilter::Allow(Vec::default()));
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let ok_response =
+            build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers.clone()));
+        assert!(rl_action
+            .process_response(ok_response)
+            .expect("is ok")
+            .is_empty());
+
+        let overlimit_response =
+            build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, Some(headers.clone()));
+        let grpc_err_response = rl_action
+            .process_response(overlimit_response)
+            .expect_err("is err");
+        assert!(grpc_err_response.headers().is_empty());
+
+        action.response_headers = Some(HeaderFilter::Deny(vec![
+            "x-ratelimit-limit".into(),
+            "x-ratelimit-remaining".into(),
+        ]));
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        let ok_response = build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers));
+        assert!(rl_action
+            .process_response(ok_response)
+            .expect("is ok")
+            .is_empty());
+    }
+
+    #[test]
+    fn process_overlimit_response_with_deny_response() {
+        let headers = vec![
+            ("content-type", "text/plain"),
+            ("x-ratelimit-remaining", "0"),
+        ];
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.deny_response = Some(DenyResponse {
+            status: 503,
+            body: r#"{"error":"slow down"}"#.into(),
+            headers: [("Content-Type".to_string(), "application/json".to_string())].into(),
+            body_expression: None,
+            header_expressions: Default::default(),
+        });
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let overlimit_response =
+            build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, Some(headers));
+        let grpc_err_response = rl_action
+            .process_response(overlimit_response)
+            .expect_err("is err");
+        assert_eq!(grpc_err_response.status_code(), 503);
+        assert_eq!(
+            grpc_err_response.headers(),
+            vec![
+                ("Content-Type", "application/json"),
+                ("x-ratelimit-remaining", "0")
+            ]
+        );
+        assert_eq!(grpc_err_response.body(), r#"{"error":"slow down"}"#);
+    }
+
+    #[test]
+    fn only_over_limit_decisions_are_cached() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.decision_cache = Some(DecisionCacheConfig {
+            ttl: Timeout(Duration::from_secs(1)),
+            capacity: None,
+        });
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        assert!(rl_action.caches_decisions());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let ok_response = build_ratelimit_response(RateLimitResponse_Code::OK, None)
+            .write_to_bytes()
+            .expect("This must serialize");
+        rl_action.cache_response(b"request", &ok_response, now);
+        assert_eq!(rl_action.cached_response(b"request", now), None);
+
+        let overlimit_response = build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, None)
+            .write_to_bytes()
+            .expect("This must serialize");
+        rl_action.cache_response(b"request", &overlimit_response, now);
+        assert_eq!(
+            rl_action.cached_response(b"request", now),
+            Some(overlimit_response)
+        );
+        assert_eq!(rl_action.cached_response(b"other request", now), None);
+        assert_eq!(
+            rl_action.cached_response(b"request", now + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn process_overlimit_response_with_deny_response_expressions() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.deny_response = Some(DenyResponse {
+            status: 429,
+            body: "Too Many Requests\n".into(),
+            headers: [("x-limit".to_string(), "unknown".to_string())].into(),
+            body_expression: Some("'Limited to ' + string(5 * 2) + ' per minute'".into()),
+            header_expressions: [
+                ("x-limit".to_string(), "5 * 2".to_string()),
+                ("x-request-id".to_string(), "request.id".to_string()),
+            ]
+            .into(),
+        });
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let overlimit_response = build_ratelimit_response(RateLimitResponse_Code::OVER_LIMIT, None);
+        let grpc_err_response = rl_action
+            .process_response(overlimit_response)
+            .expect_err("is err");
+        assert_eq!(grpc_err_response.status_code(), 429);
+        // request.id isn't available, the header without a static value is left out
+        assert_eq!(grpc_err_response.headers(), vec![("x-limit", "10")]);
+        assert_eq!(grpc_err_response.body(), "Limited to 10 per minute");
+
+        action.deny_response = Some(DenyResponse {
+            status: 429,
+            body: "Too Many Requests\n".into(),
+            headers: Default::default(),
+            body_expression: Some("'(' +".into()),
+            header_expressions: Default::default(),
+        });
+        assert!(RateLimitAction::new(&action, &build_service()).is_err());
+    }
+
+    #[test]
+    fn reset_headers_are_formatted_as_configured() {
+        let headers = vec![
+            ("X-RateLimit-Limit", "10"),
+            ("X-RateLimit-Reset", "60"),
+            ("ratelimit-reset", "60"),
+        ];
+        let received_at = || Some(1_700_000_000);
+        let mut action = build_action(Vec::default(), Vec::default());
+
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        let ok_response =
+            build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers.clone()));
+        assert_eq!(
+            rl_action.get_header_vec_received_at(ok_response.response_headers_to_add, received_at),
+            vec![
+                ("X-RateLimit-Limit".to_string(), "10".to_string()),
+                ("X-RateLimit-Reset".to_string(), "60".to_string()),
+                ("ratelimit-reset".to_string(), "60".to_string()),
+            ]
+        );
+
+        action.rate_limit_reset_format = ResetFormat::Epoch;
+        let rl_action = RateLimitAction::new(&action, &build_service())
+            .expect("action building failed. Maybe predicates compilation?");
+        let ok_response =
+            build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers.clone()));
+        assert_eq!(
+            rl_action.get_header_vec_received_at(ok_response.response_headers_to_add, received_at),
+            vec![
+                ("X-RateLimit-Limit".to_string(), "10".to_string()),
+                ("X-RateLimit-Reset".to_string(), "1700000060".to_string()),
+                ("ratelimit-reset".to_string(), "1700000060".to_string()),
+            ]
+        );
+
+        // left as they are when the time of the request isn't known
+        let ok_response = build_ratelimit_response(RateLimitResponse_Code::OK, Some(headers));
+        assert_eq!(
+            rl_action.get_header_vec_received_at(ok_response.response_headers_to_add, || None),
+            vec![
+                ("X-RateLimit-Limit".to_string(), "10".to_string()),
+                ("X-RateLimit-Reset".to_string(), "60".to_string()),
+                ("ratelimit-reset".to_string(), "60".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn process_error_response() {
         let action = build_action(Vec::default(), Vec::default());
@@ -507,4 +1253,110 @@ mod test {
         let headers = result.expect("is ok");
         assert!(headers.is_empty());
     }
+
+    #[test]
+    fn process_error_response_as_passthrough() {
+        let action = build_action(Vec::default(), Vec::default());
+        let passthrough_service = build_service_with_failure_mode(FailureMode::Passthrough);
+        let rl_action = RateLimitAction::new(&action, &passthrough_service)
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let error_response = build_ratelimit_response(RateLimitResponse_Code::UNKNOWN, None);
+        let result = rl_action.process_response(error_response);
+        assert!(result.is_ok());
+
+        let headers = result.expect("is ok");
+        assert_eq!(
+            headers,
+            vec![("x-kuadrant-degraded".to_string(), "true".to_string())]
+        );
+
+        let mut passthrough_service = passthrough_service;
+        passthrough_service.degraded_header = Some("x-ratelimit-skipped".to_string());
+        let rl_action = RateLimitAction::new(&action, &passthrough_service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let error_response = build_ratelimit_response(RateLimitResponse_Code::UNKNOWN, None);
+        assert_eq!(
+            rl_action.process_response(error_response).expect("is ok"),
+            vec![("x-ratelimit-skipped".to_string(), "true".to_string())]
+        );
+    }
+
+    #[test]
+    fn domain_is_scoped_to_a_valid_tenant() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.tenant_scope = Some(TenantScope {
+            expression: "'acme'".into(),
+            allowed_pattern: "[a-z0-9-]+".into(),
+        });
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert_eq!(rl_action.domain().expect("is ok"), "some_scope/acme");
+    }
+
+    #[test]
+    fn domain_is_the_resolved_scope() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.scope_expression = Some("'api-' + 'v1'".into());
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert_eq!(rl_action.scope(), "some_scope");
+        assert_eq!(rl_action.domain().expect("is ok"), "api-v1");
+
+        action.tenant_scope = Some(TenantScope {
+            expression: "'acme'".into(),
+            allowed_pattern: "[a-z0-9-]+".into(),
+        });
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert_eq!(rl_action.domain().expect("is ok"), "api-v1/acme");
+    }
+
+    #[test]
+    fn domain_rejects_an_empty_scope() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.scope_expression = Some("''".into());
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        let grpc_err_response = rl_action.domain().expect_err("is err");
+        assert_eq!(
+            grpc_err_response.status_code(),
+            StatusCode::InternalServerError as u32
+        );
+
+        action.scope_expression = Some("42".into());
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert!(rl_action.domain().is_err());
+    }
+
+    #[test]
+    fn domain_rejects_an_invalid_tenant() {
+        let mut action = build_action(Vec::default(), Vec::default());
+        action.tenant_scope = Some(TenantScope {
+            expression: "'acme/../other'".into(),
+            allowed_pattern: "[a-z0-9-]+".into(),
+        });
+        let service = build_service();
+        let rl_action = RateLimitAction::new(&action, &service)
+            .expect("action building failed. Maybe predicates compilation?");
+
+        let grpc_err_response = rl_action.domain().expect_err("is err");
+        assert_eq!(
+            grpc_err_response.status_code(),
+            StatusCode::Forbidden as u32
+        );
+    }
+
+    #[test]
+    fn access_suffix_depends_on_the_method() {
+        assert_eq!(access_suffix("GET"), ":read");
+        assert_eq!(access_suffix("HEAD"), ":read");
+        assert_eq!(access_suffix("POST"), ":write");
+        assert_eq!(access_suffix("DELETE"), ":write");
+        assert_eq!(access_suffix(""), ":write");
+    }
 }
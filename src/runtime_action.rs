@@ -1,13 +1,16 @@
 use crate::auth_action::AuthAction;
-use crate::configuration::{Action, FailureMode, Service, ServiceType};
+use crate::configuration::{Action, FailureMode, OnTimeout, Service, ServiceType};
+use crate::envoy::{CheckResponse, RateLimitResponse, RateLimitResponse_Code};
 use crate::ratelimit_action::RateLimitAction;
 use crate::service::auth::AuthService;
 use crate::service::rate_limit::RateLimitService;
 use crate::service::{GrpcErrResponse, GrpcRequest, GrpcService, Headers};
 use log::debug;
 use protobuf::Message;
+use serde_json::{json, Value as JsonValue};
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::time::SystemTime;
 
 #[derive(Debug)]
 pub enum RuntimeAction {
@@ -17,13 +20,28 @@ pub enum RuntimeAction {
 
 impl RuntimeAction {
     pub fn new(action: &Action, services: &HashMap<String, Service>) -> Result<Self, String> {
-        let service = services
+        let mut service = services
             .get(&action.service)
-            .ok_or(format!("Unknown service: {}", action.service))?;
+            .ok_or(format!("Unknown service: {}", action.service))?
+            .clone();
+        if action.failure_mode.is_some() {
+            service.failure_mode = action.failure_mode;
+        }
+        if let Some(timeout) = &action.timeout {
+            service.timeout = timeout.clone();
+        }
 
         match service.service_type {
-            ServiceType::RateLimit => Ok(Self::RateLimit(RateLimitAction::new(action, service)?)),
-            ServiceType::Auth => Ok(Self::Auth(AuthAction::new(action, service)?)),
+            ServiceType::RateLimit => Ok(Self::RateLimit(RateLimitAction::new(action, &service)?)),
+            ServiceType::Auth => Ok(Self::Auth(AuthAction::new(action, &service)?)),
+        }
+    }
+
+    /// The kind of service the action calls, as reported in explanations and metrics
+    pub fn service_type(&self) -> &'static str {
+        match self {
+            Self::Auth(_) => "auth",
+            Self::RateLimit(_) => "ratelimit",
         }
     }
 
@@ -41,6 +59,41 @@ impl RuntimeAction {
         }
     }
 
+    /// Whether the action runs on the response, rather than on the request
+    pub fn is_deferred(&self) -> bool {
+        match self {
+            Self::Auth(_) => false,
+            Self::RateLimit(rl_action) => rl_action.is_deferred(),
+        }
+    }
+
+    /// Whether the action runs at the end of the response body, rather than on its headers
+    pub fn awaits_response_body(&self) -> bool {
+        match self {
+            Self::Auth(_) => false,
+            Self::RateLimit(rl_action) => rl_action.awaits_response_body(),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        match self {
+            Self::Auth(_) => false,
+            Self::RateLimit(rl_action) => rl_action.is_dry_run(),
+        }
+    }
+
+    fn response_conditions_apply(&self) -> bool {
+        match self {
+            Self::Auth(_) => true,
+            Self::RateLimit(rl_action) => rl_action.response_conditions_apply(),
+        }
+    }
+
+    /// Headers flagging a failure let through as `FailureMode::Passthrough`
+    pub fn degraded_headers(&self) -> Headers {
+        self.grpc_service().degraded_headers()
+    }
+
     pub fn get_failure_mode(&self) -> FailureMode {
         match self {
             Self::Auth(auth_action) => auth_action.get_failure_mode(),
@@ -48,6 +101,38 @@ impl RuntimeAction {
         }
     }
 
+    pub fn caches_decisions(&self) -> bool {
+        match self {
+            Self::Auth(_) => false,
+            Self::RateLimit(rl_action) => rl_action.caches_decisions(),
+        }
+    }
+
+    pub fn cached_response(&self, request: &[u8], now: SystemTime) -> Option<Vec<u8>> {
+        match self {
+            Self::Auth(_) => None,
+            Self::RateLimit(rl_action) => rl_action.cached_response(request, now),
+        }
+    }
+
+    pub fn cache_response(&self, request: &[u8], response: &[u8], now: SystemTime) {
+        if let Self::RateLimit(rl_action) = self {
+            rl_action.cache_response(request, response, now)
+        }
+    }
+
+    pub fn get_on_timeout(&self) -> Option<OnTimeout> {
+        self.grpc_service().get_on_timeout()
+    }
+
+    pub fn get_max_response_size(&self) -> Option<usize> {
+        self.grpc_service().get_max_response_size()
+    }
+
+    pub fn get_retries(&self) -> u32 {
+        self.grpc_service().get_retries()
+    }
+
     #[must_use]
     pub fn merge(&mut self, other: RuntimeAction) -> Option<RuntimeAction> {
         // only makes sense for rate limiting actions
@@ -59,38 +144,60 @@ impl RuntimeAction {
         Some(other)
     }
 
-    pub fn process_request(&self) -> Option<GrpcRequest> {
-        if !self.conditions_apply() {
-            None
+    pub fn process_request(&self) -> Result<Option<GrpcRequest>, GrpcErrResponse> {
+        if !self.conditions_apply() || !self.response_conditions_apply() {
+            Ok(None)
         } else {
-            self.grpc_service().build_request(self.build_message())
+            Ok(self.grpc_service().build_request(self.build_message()?))
         }
     }
 
-    pub fn process_response(&self, msg: &[u8]) -> Result<Headers, GrpcErrResponse> {
+    /// The headers to add to the response, and whether they flag a failure let through as
+    /// `FailureMode::Passthrough`
+    pub fn process_response(&self, msg: &[u8]) -> Result<(Headers, bool), GrpcErrResponse> {
+        let passthrough = self.get_failure_mode() == FailureMode::Passthrough;
         match self {
-            Self::Auth(auth_action) => match Message::parse_from_bytes(msg) {
-                Ok(check_response) => auth_action.process_response(check_response),
+            Self::Auth(auth_action) => match CheckResponse::parse_from_bytes(msg) {
+                Ok(check_response) => {
+                    let failed = check_response.http_response.is_none();
+                    auth_action
+                        .process_response(check_response)
+                        .map(|headers| (headers, failed && passthrough))
+                }
                 Err(e) => {
                     debug!("process_response(auth): failed to parse response `{e:?}`");
                     match self.get_failure_mode() {
                         FailureMode::Deny => Err(GrpcErrResponse::new_internal_server_error()),
                         FailureMode::Allow => {
                             debug!("process_response(auth): continuing as FailureMode Allow");
-                            Ok(Vec::default())
+                            Ok((Vec::default(), false))
+                        }
+                        FailureMode::Passthrough => {
+                            debug!("process_response(auth): continuing as FailureMode Passthrough");
+                            Ok((self.degraded_headers(), true))
                         }
                     }
                 }
             },
-            Self::RateLimit(rl_action) => match Message::parse_from_bytes(msg) {
-                Ok(rate_limit_response) => rl_action.process_response(rate_limit_response),
+            Self::RateLimit(rl_action) => match RateLimitResponse::parse_from_bytes(msg) {
+                Ok(rate_limit_response) => {
+                    let failed =
+                        rate_limit_response.get_overall_code() == RateLimitResponse_Code::UNKNOWN;
+                    rl_action
+                        .process_response(rate_limit_response)
+                        .map(|headers| (headers, failed && passthrough))
+                }
                 Err(e) => {
                     debug!("process_response(rl): failed to parse response `{e:?}`");
                     match self.get_failure_mode() {
                         FailureMode::Deny => Err(GrpcErrResponse::new_internal_server_error()),
                         FailureMode::Allow => {
                             debug!("process_response(rl): continuing as FailureMode Allow");
-                            Ok(Vec::default())
+                            Ok((Vec::default(), false))
+                        }
+                        FailureMode::Passthrough => {
+                            debug!("process_response(rl): continuing as FailureMode Passthrough");
+                            Ok((self.degraded_headers(), true))
                         }
                     }
                 }
@@ -98,22 +205,66 @@ impl RuntimeAction {
         }
     }
 
-    pub fn build_message(&self) -> Option<Vec<u8>> {
+    /// What the action would send, short of sending it
+    pub fn explain(&self) -> JsonValue {
+        let applies = self.conditions_apply();
+        match self {
+            Self::Auth(auth_action) => json!({
+                "type": self.service_type(),
+                "scope": auth_action.scope(),
+                "applies": applies,
+            }),
+            Self::RateLimit(rl_action) => {
+                let mut explanation = json!({
+                    "type": self.service_type(),
+                    "scope": rl_action.scope(),
+                    "applies": applies,
+                });
+                if rl_action.is_deferred() {
+                    // its response predicates can't be told before the response
+                    explanation["deferred"] = true.into();
+                }
+                if rl_action.is_dry_run() {
+                    explanation["dryRun"] = true.into();
+                }
+                if applies {
+                    explanation["domain"] = match rl_action.domain() {
+                        Ok(domain) => domain.into(),
+                        Err(err) => json!({ "denied": err.status_code() }),
+                    };
+                    // no request is sent for an empty descriptor
+                    explanation["descriptor"] = rl_action
+                        .build_descriptor()
+                        .get_entries()
+                        .iter()
+                        .map(|entry| json!({ "key": entry.key, "value": entry.value }))
+                        .collect();
+                }
+                explanation
+            }
+        }
+    }
+
+    pub fn build_message(&self) -> Result<Option<Vec<u8>>, GrpcErrResponse> {
         match self {
             RuntimeAction::RateLimit(rl_action) => {
                 let descriptor = rl_action.build_descriptor();
                 if descriptor.entries.is_empty() {
                     debug!("build_message(rl): empty descriptors");
-                    None
+                    Ok(None)
                 } else {
-                    RateLimitService::request_message_as_bytes(
-                        String::from(rl_action.scope()),
+                    Ok(RateLimitService::request_message_as_bytes(
+                        rl_action.domain()?,
                         vec![descriptor].into(),
-                    )
+                    ))
                 }
             }
             RuntimeAction::Auth(auth_action) => {
-                AuthService::request_message_as_bytes(String::from(auth_action.scope()))
+                let grpc_service = auth_action.get_grpcservice();
+                Ok(AuthService::request_message_as_bytes(
+                    String::from(auth_action.scope()),
+                    grpc_service.get_field_mask(),
+                ))
             }
         }
     }
@@ -122,14 +273,22 @@ impl RuntimeAction {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::configuration::{Action, FailureMode, ServiceType, Timeout};
+    use crate::configuration::{
+        Action, EnforcementMode, FailureMode, ResetFormat, ServiceType, Timeout,
+    };
+    use std::time::Duration;
 
     fn build_rl_service() -> Service {
         Service {
             service_type: ServiceType::RateLimit,
             endpoint: "limitador".into(),
-            failure_mode: FailureMode::default(),
+            failure_mode: None,
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         }
     }
 
@@ -137,8 +296,13 @@ mod test {
         Service {
             service_type: ServiceType::Auth,
             endpoint: "authorino".into(),
-            failure_mode: FailureMode::default(),
+            failure_mode: None,
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         }
     }
 
@@ -146,11 +310,48 @@ mod test {
         Action {
             service: service.into(),
             scope: scope.into(),
+            scope_expression: None,
             predicates: Vec::default(),
             data: Vec::default(),
+            tenant_scope: None,
+            read_write_domains: false,
+            response_predicates: Vec::default(),
+            enforcement_mode: EnforcementMode::default(),
+            response_headers: None,
+            rate_limit_headers_prefix: None,
+            on_response_body: false,
+            on_response_trailers: false,
+            failure_mode: None,
+            timeout: None,
+            rate_limit_reset_format: ResetFormat::default(),
+            deny_response: None,
+            decision_cache: None,
         }
     }
 
+    #[test]
+    fn actions_override_the_timeout_of_the_service() {
+        let mut services = HashMap::new();
+        services.insert(String::from("service_auth"), build_auth_service());
+
+        let action = build_action("service_auth", "scope");
+        let runtime_action = RuntimeAction::new(&action, &services)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert_eq!(
+            runtime_action.grpc_service().get_timeout(),
+            Timeout::default().0
+        );
+
+        let mut action = build_action("service_auth", "scope");
+        action.timeout = Some(Timeout(Duration::from_millis(250)));
+        let runtime_action = RuntimeAction::new(&action, &services)
+            .expect("action building failed. Maybe predicates compilation?");
+        assert_eq!(
+            runtime_action.grpc_service().get_timeout(),
+            Duration::from_millis(250)
+        );
+    }
+
     #[test]
     fn only_rl_actions_are_merged() {
         let mut services = HashMap::new();
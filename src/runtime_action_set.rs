@@ -1,7 +1,12 @@
 use crate::configuration::{ActionSet, Service};
-use crate::data::{Predicate, PredicateVec};
+use crate::connection_counter::ConnectionCounter;
+use crate::data::{get_attribute, Predicate, PredicateVec};
+use crate::envoy::StatusCode;
 use crate::runtime_action::RuntimeAction;
 use crate::service::{GrpcErrResponse, Headers, IndexedGrpcRequest};
+use log::{debug, warn};
+use serde_json::{json, Value as JsonValue};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -10,6 +15,38 @@ pub struct RuntimeActionSet {
     pub name: String,
     pub route_rule_predicates: Vec<Predicate>,
     pub runtime_actions: Vec<Rc<RuntimeAction>>,
+    requirements: Vec<Requirement>,
+    max_concurrency: Option<u32>,
+    in_flight: Cell<u32>,
+    max_requests_per_connection: Option<u32>,
+    connection_counter: RefCell<ConnectionCounter>,
+}
+
+// Checked before any action is run, denying the requests that don't meet it
+#[derive(Debug)]
+struct Requirement {
+    predicate: Predicate,
+    status: u32,
+    reason: &'static str,
+    body: &'static str,
+}
+
+/// When actions run: on the request headers, or deferred to the response headers or body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Request,
+    Response,
+    ResponseBody,
+}
+
+/// A request in flight for an action set, until dropped
+#[derive(Debug)]
+pub struct InFlight(Rc<RuntimeActionSet>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.set(self.0.in_flight.get() - 1);
+    }
 }
 
 impl RuntimeActionSet {
@@ -23,18 +60,62 @@ impl RuntimeActionSet {
             route_rule_predicates
                 .push(Predicate::route_rule(predicate).map_err(|e| e.to_string())?);
         }
+        if let Some(max_header_size) = action_set.route_rule_conditions.max_header_size {
+            route_rule_predicates.push(
+                Predicate::route_rule(&format!("request.header_size <= {max_header_size}"))
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        if action_set.route_rule_conditions.skip_websocket_upgrades {
+            route_rule_predicates.push(
+                Predicate::route_rule("!request.websocket_upgrade").map_err(|e| e.to_string())?,
+            );
+        }
 
         // actions
         let mut all_runtime_actions = Vec::default();
         for action in action_set.actions.iter() {
-            all_runtime_actions.push(RuntimeAction::new(action, services)?);
+            if action.deny_response.is_none() && action_set.deny_response.is_some() {
+                let mut action = action.clone();
+                action.deny_response = action_set.deny_response.clone();
+                all_runtime_actions.push(RuntimeAction::new(&action, services)?);
+            } else {
+                all_runtime_actions.push(RuntimeAction::new(action, services)?);
+            }
         }
         let runtime_actions = Self::merge_subsequent_actions_of_a_kind(all_runtime_actions);
 
+        let mut requirements = Vec::default();
+        if let Some(mtls) = &action_set.mtls {
+            requirements.push(Requirement {
+                predicate: Predicate::new("connection.mtls == true").map_err(|e| e.to_string())?,
+                status: mtls.status,
+                reason: "request not made over mTLS",
+                body: "No Cert\n",
+            });
+        }
+        if let Some(sni_match) = &action_set.sni_match {
+            requirements.push(Requirement {
+                predicate: Predicate::new(&format!(
+                    "hostMatchesSni({})",
+                    sni_match.allow_without_sni
+                ))
+                .map_err(|e| e.to_string())?,
+                status: sni_match.status,
+                reason: "request host does not match the TLS SNI",
+                body: "Misdirected Request\n",
+            });
+        }
+
         Ok(Self {
             name: action_set.name.clone(),
             route_rule_predicates,
             runtime_actions: runtime_actions.into_iter().map(Rc::new).collect(),
+            requirements,
+            max_concurrency: action_set.max_concurrency,
+            in_flight: Cell::new(0),
+            max_requests_per_connection: action_set.max_requests_per_connection,
+            connection_counter: RefCell::default(),
         })
     }
 
@@ -60,32 +141,185 @@ impl RuntimeActionSet {
         self.route_rule_predicates.apply()
     }
 
-    pub fn find_first_grpc_request(&self) -> Option<IndexedGrpcRequest> {
-        self.find_next_grpc_request(0)
+    /// Denies the request locally when it doesn't meet the action set's requirements
+    pub fn check_requirements(&self) -> Result<(), GrpcErrResponse> {
+        for requirement in &self.requirements {
+            if !requirement.predicate.test().unwrap_or_else(|err| {
+                warn!(
+                    "failed to evaluate requirement `{}`: {err}",
+                    requirement.predicate.source()
+                );
+                false
+            }) {
+                debug!("check_requirements: {}", requirement.reason);
+                return Err(GrpcErrResponse::new(
+                    requirement.status,
+                    Vec::default(),
+                    requirement.body.to_string(),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    pub fn find_next_grpc_request(&self, start: usize) -> Option<IndexedGrpcRequest> {
+    /// Counts the request in flight until the returned guard is dropped, unless the action set
+    /// is already at its max concurrency
+    pub fn enter(self: &Rc<Self>) -> Result<InFlight, GrpcErrResponse> {
+        let in_flight = self.in_flight.get();
+        if let Some(max_concurrency) = self.max_concurrency {
+            if in_flight >= max_concurrency {
+                debug!(
+                    "enter: {} requests already in flight for {}",
+                    in_flight, self.name
+                );
+                return Err(GrpcErrResponse::new(
+                    StatusCode::ServiceUnavailable as u32,
+                    Vec::default(),
+                    "Service Unavailable\n".to_string(),
+                ));
+            }
+        }
+        self.in_flight.set(in_flight + 1);
+        Ok(InFlight(Rc::clone(self)))
+    }
+
+    /// Counts the request on its downstream connection, denying it once the connection made
+    /// as many as allowed, and returning the connection it was counted on
+    pub fn count_connection_request(&self) -> Result<Option<u64>, GrpcErrResponse> {
+        if self.max_requests_per_connection.is_none() {
+            return Ok(None);
+        }
+        match get_attribute::<u64>(&"connection.id".into()) {
+            Ok(Some(connection_id)) => self
+                .admit_connection_request(connection_id)
+                .map(|()| Some(connection_id)),
+            Ok(None) => {
+                warn!("count_connection_request: connection.id not found");
+                Ok(None)
+            }
+            Err(e) => {
+                warn!("count_connection_request: failed to retrieve connection.id: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stops counting the requests of a connection, once closed
+    pub fn forget_connection(&self, connection_id: u64) {
+        self.connection_counter.borrow_mut().remove(connection_id);
+    }
+
+    fn admit_connection_request(&self, connection_id: u64) -> Result<(), GrpcErrResponse> {
+        let count = self
+            .connection_counter
+            .borrow_mut()
+            .increment(connection_id);
+        match self.max_requests_per_connection {
+            Some(max) if count > max => {
+                debug!(
+                    "count_connection_request: {} requests on connection {connection_id} for {}",
+                    count, self.name
+                );
+                Err(GrpcErrResponse::new(
+                    StatusCode::TooManyRequests as u32,
+                    Vec::default(),
+                    "Too Many Requests\n".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How the request would be handled, short of calling the services, so that explaining it
+    /// doesn't count towards any limit
+    pub fn explain(&self) -> JsonValue {
+        json!({
+            "actionSet": self.name,
+            "routeRuleConditions": self
+                .route_rule_predicates
+                .iter()
+                .map(|predicate| match predicate.test() {
+                    Ok(result) => json!({ "predicate": predicate.source(), "result": result }),
+                    Err(err) => json!({ "predicate": predicate.source(), "error": err }),
+                })
+                .collect::<Vec<_>>(),
+            "requirements": match self.check_requirements() {
+                Ok(()) => json!("met"),
+                Err(err) => json!({ "denied": err.status_code() }),
+            },
+            "actions": self
+                .runtime_actions
+                .iter()
+                .map(|action| action.explain())
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn find_first_grpc_request(&self) -> Result<Option<IndexedGrpcRequest>, GrpcErrResponse> {
+        self.find_next_grpc_request(0, Phase::Request)
+    }
+
+    pub fn find_first_response_grpc_request(
+        &self,
+    ) -> Result<Option<IndexedGrpcRequest>, GrpcErrResponse> {
+        self.find_next_grpc_request(0, Phase::Response)
+    }
+
+    pub fn find_first_response_body_grpc_request(
+        &self,
+    ) -> Result<Option<IndexedGrpcRequest>, GrpcErrResponse> {
+        self.find_next_grpc_request(0, Phase::ResponseBody)
+    }
+
+    pub fn has_deferred_actions(&self) -> bool {
         self.runtime_actions
             .iter()
-            .skip(start)
-            .enumerate()
-            .find_map(|(i, action)| {
-                action
-                    .process_request()
-                    .map(|request| IndexedGrpcRequest::new(start + i, request))
-            })
+            .any(|action| action.is_deferred())
+    }
+
+    pub fn has_response_body_actions(&self) -> bool {
+        self.runtime_actions
+            .iter()
+            .any(|action| action.awaits_response_body())
+    }
+
+    /// The phase the action at `index` runs in
+    pub fn phase_of(&self, index: usize) -> Phase {
+        if self.runtime_actions[index].awaits_response_body() {
+            Phase::ResponseBody
+        } else if self.runtime_actions[index].is_deferred() {
+            Phase::Response
+        } else {
+            Phase::Request
+        }
+    }
+
+    pub fn find_next_grpc_request(
+        &self,
+        start: usize,
+        phase: Phase,
+    ) -> Result<Option<IndexedGrpcRequest>, GrpcErrResponse> {
+        for (i, action) in self.runtime_actions.iter().enumerate().skip(start) {
+            if self.phase_of(i) != phase {
+                continue;
+            }
+            if let Some(request) = action.process_request()? {
+                return Ok(Some(IndexedGrpcRequest::new(i, request)));
+            }
+        }
+        Ok(None)
     }
 
     pub fn process_grpc_response(
         &self,
         index: usize,
         msg: &[u8],
-    ) -> Result<(Option<IndexedGrpcRequest>, Headers), GrpcErrResponse> {
+    ) -> Result<(Option<IndexedGrpcRequest>, Headers, bool), GrpcErrResponse> {
         self.runtime_actions[index]
             .process_response(msg)
-            .map(|headers| {
-                let next_msg = self.find_next_grpc_request(index + 1);
-                (next_msg, headers)
+            .and_then(|(headers, degraded)| {
+                let next_msg = self.find_next_grpc_request(index + 1, self.phase_of(index))?;
+                Ok((next_msg, headers, degraded))
             })
     }
 }
@@ -94,7 +328,8 @@ impl RuntimeActionSet {
 mod test {
     use super::*;
     use crate::configuration::{
-        Action, ActionSet, FailureMode, RouteRuleConditions, ServiceType, Timeout,
+        Action, ActionSet, DataItem, DataType, EnforcementMode, FailureMode, ResetFormat,
+        RouteRuleConditions, ServiceType, StaticItem, Timeout,
     };
 
     #[test]
@@ -112,8 +347,10 @@ mod test {
         let action_set = ActionSet::new(
             "some_name".to_owned(),
             RouteRuleConditions {
-                hostnames: Vec::default(),
+                hostnames: None,
                 predicates: vec!["true".into(), "true".into()],
+                max_header_size: None,
+                skip_websocket_upgrades: false,
             },
             Vec::new(),
         );
@@ -129,8 +366,10 @@ mod test {
         let action_set = ActionSet::new(
             "some_name".to_owned(),
             RouteRuleConditions {
-                hostnames: Vec::default(),
+                hostnames: None,
                 predicates: vec!["true".into(), "true".into(), "true".into(), "false".into()],
+                max_header_size: None,
+                skip_websocket_upgrades: false,
             },
             Vec::new(),
         );
@@ -141,14 +380,38 @@ mod test {
         assert!(!runtime_action_set.conditions_apply())
     }
 
+    #[test]
+    fn max_header_size_applies_as_a_route_condition() {
+        // the test host's request headers are `X-Auth: kuadrant`, i.e. 14 bytes
+        for (max_header_size, applies) in [(14, true), (13, false)] {
+            let action_set = ActionSet::new(
+                "some_name".to_owned(),
+                RouteRuleConditions {
+                    hostnames: None,
+                    predicates: Vec::default(),
+                    max_header_size: Some(max_header_size),
+                    skip_websocket_upgrades: false,
+                },
+                Vec::new(),
+            );
+
+            let runtime_action_set = RuntimeActionSet::new(&action_set, &HashMap::default())
+                .expect("should not happen from an empty set of actions");
+
+            assert_eq!(runtime_action_set.conditions_apply(), applies);
+        }
+    }
+
     #[test]
     #[should_panic]
     fn when_a_cel_expression_does_not_evaluate_to_bool_panics() {
         let action_set = ActionSet::new(
             "some_name".to_owned(),
             RouteRuleConditions {
-                hostnames: Vec::default(),
+                hostnames: None,
                 predicates: vec!["true".into(), "true".into(), "true".into(), "1".into()],
+                max_header_size: None,
+                skip_websocket_upgrades: false,
             },
             Vec::new(),
         );
@@ -158,12 +421,85 @@ mod test {
         runtime_action_set.conditions_apply();
     }
 
+    #[test]
+    fn requests_past_max_concurrency_are_denied_until_some_complete() {
+        let mut action_set = ActionSet::new("some_name".to_owned(), Default::default(), Vec::new());
+        action_set.max_concurrency = Some(2);
+        let runtime_action_set = Rc::new(
+            RuntimeActionSet::new(&action_set, &HashMap::default())
+                .expect("should not happen from an empty set of actions"),
+        );
+
+        let first = runtime_action_set.enter().expect("below max concurrency");
+        let _second = runtime_action_set.enter().expect("below max concurrency");
+        let denied = runtime_action_set.enter().expect_err("at max concurrency");
+        assert_eq!(denied.status_code(), StatusCode::ServiceUnavailable as u32);
+
+        drop(first);
+        let _third = runtime_action_set.enter().expect("a request completed");
+        assert!(runtime_action_set.enter().is_err());
+    }
+
+    #[test]
+    fn without_max_concurrency_requests_are_not_limited() {
+        let action_set = ActionSet::new("some_name".to_owned(), Default::default(), Vec::new());
+        let runtime_action_set = Rc::new(
+            RuntimeActionSet::new(&action_set, &HashMap::default())
+                .expect("should not happen from an empty set of actions"),
+        );
+
+        let in_flight: Vec<InFlight> = (0..100)
+            .map(|_| runtime_action_set.enter().expect("not limited"))
+            .collect();
+        assert_eq!(runtime_action_set.in_flight.get(), 100);
+        drop(in_flight);
+        assert_eq!(runtime_action_set.in_flight.get(), 0);
+    }
+
+    #[test]
+    fn requests_past_max_per_connection_are_denied() {
+        let mut action_set = ActionSet::new("some_name".to_owned(), Default::default(), Vec::new());
+        action_set.max_requests_per_connection = Some(2);
+        let runtime_action_set = RuntimeActionSet::new(&action_set, &HashMap::default())
+            .expect("should not happen from an empty set of actions");
+
+        assert!(runtime_action_set.admit_connection_request(1).is_ok());
+        assert!(runtime_action_set.admit_connection_request(1).is_ok());
+        let denied = runtime_action_set
+            .admit_connection_request(1)
+            .expect_err("at max requests for the connection");
+        assert_eq!(denied.status_code(), StatusCode::TooManyRequests as u32);
+
+        // other connections are counted on their own
+        assert!(runtime_action_set.admit_connection_request(2).is_ok());
+        assert!(runtime_action_set.admit_connection_request(1).is_err());
+
+        // the connection closing, its id may be reused by a new one
+        runtime_action_set.forget_connection(1);
+        assert!(runtime_action_set.admit_connection_request(1).is_ok());
+    }
+
+    #[test]
+    fn without_max_per_connection_requests_are_not_counted() {
+        let action_set = ActionSet::new("some_name".to_owned(), Default::default(), Vec::new());
+        let runtime_action_set = RuntimeActionSet::new(&action_set, &HashMap::default())
+            .expect("should not happen from an empty set of actions");
+
+        assert!(runtime_action_set.count_connection_request().is_ok());
+        assert_eq!(runtime_action_set.connection_counter.borrow().tracked(), 0);
+    }
+
     fn build_rl_service() -> Service {
         Service {
             service_type: ServiceType::RateLimit,
             endpoint: "limitador".into(),
-            failure_mode: FailureMode::default(),
+            failure_mode: None,
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         }
     }
 
@@ -171,8 +507,13 @@ mod test {
         Service {
             service_type: ServiceType::Auth,
             endpoint: "authorino".into(),
-            failure_mode: FailureMode::default(),
+            failure_mode: None,
             timeout: Timeout::default(),
+            on_timeout: None,
+            max_response_size: None,
+            field_mask: None,
+            retries: None,
+            degraded_header: None,
         }
     }
 
@@ -180,11 +521,75 @@ mod test {
         Action {
             service: service.into(),
             scope: scope.into(),
+            scope_expression: None,
             predicates: Vec::default(),
             data: Vec::default(),
+            tenant_scope: None,
+            read_write_domains: false,
+            response_predicates: Vec::default(),
+            enforcement_mode: EnforcementMode::default(),
+            response_headers: None,
+            rate_limit_headers_prefix: None,
+            on_response_body: false,
+            on_response_trailers: false,
+            failure_mode: None,
+            timeout: None,
+            rate_limit_reset_format: ResetFormat::default(),
+            deny_response: None,
+            decision_cache: None,
         }
     }
 
+    #[test]
+    fn explains_how_a_request_would_be_handled() {
+        let mut rl_action = build_action("limitador", "rlp-ns-A/rlp-name-A");
+        rl_action.data = vec![DataItem {
+            item: DataType::Static(StaticItem {
+                key: "rlp-ns-A/rlp-name-A".into(),
+                value: "1".into(),
+            }),
+        }];
+        let mut auth_action = build_action("authorino", "authconfig-A");
+        auth_action.predicates = vec!["false".into()];
+        let action_set = ActionSet::new(
+            "some_name".to_owned(),
+            RouteRuleConditions {
+                hostnames: None,
+                predicates: vec!["1 + 1 == 2".into()],
+                max_header_size: None,
+                skip_websocket_upgrades: false,
+            },
+            vec![auth_action, rl_action],
+        );
+
+        let mut services = HashMap::new();
+        services.insert(String::from("limitador"), build_rl_service());
+        services.insert(String::from("authorino"), build_auth_service());
+        let runtime_action_set = RuntimeActionSet::new(&action_set, &services)
+            .expect("should not happen from simple actions");
+
+        assert_eq!(
+            runtime_action_set.explain(),
+            json!({
+                "actionSet": "some_name",
+                "routeRuleConditions": [
+                    { "predicate": "1 + 1 == 2", "result": true },
+                ],
+                "requirements": "met",
+                "actions": [
+                    { "type": "auth", "scope": "authconfig-A", "applies": false },
+                    {
+                        "type": "ratelimit",
+                        "scope": "rlp-ns-A/rlp-name-A",
+                        "applies": true,
+                        "domain": "rlp-ns-A/rlp-name-A",
+                        "descriptor": [{ "key": "rlp-ns-A/rlp-name-A", "value": "1" }],
+                    },
+                ],
+            })
+        );
+    }
+
     #[test]
     fn simple_folding() {
         let action_a = build_action("rl_service_common", "scope_common");
@@ -1,6 +1,10 @@
 use crate::action_set_index::ActionSetIndex;
-use crate::configuration::PluginConfiguration;
+use crate::configuration::{DataType, FailureMode, PluginConfiguration, SelectionMode, Service};
+use crate::data::Expression;
 use crate::runtime_action_set::RuntimeActionSet;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
 impl TryFrom<PluginConfiguration> for ActionSetIndex {
@@ -9,9 +13,41 @@ impl TryFrom<PluginConfiguration> for ActionSetIndex {
     fn try_from(config: PluginConfiguration) -> Result<Self, Self::Error> {
         let mut index = ActionSetIndex::new();
         for action_set in config.action_sets.iter() {
-            let runtime_action_set = Rc::new(RuntimeActionSet::new(action_set, &config.services)?);
-            for hostname in action_set.route_rule_conditions.hostnames.iter() {
-                index.insert(hostname, Rc::clone(&runtime_action_set));
+            if let Some(max) = config
+                .max_actions
+                .filter(|max| action_set.actions.len() > *max)
+            {
+                return Err(format!(
+                    "Action set `{}` has {} actions, over the maximum of {max}",
+                    action_set.name,
+                    action_set.actions.len()
+                ));
+            }
+            if config.selection_mode == SelectionMode::All
+                && action_set.actions.iter().any(|action| {
+                    !action.response_predicates.is_empty()
+                        || action.on_response_body
+                        || action.on_response_trailers
+                })
+            {
+                return Err(format!(
+                    "Action set `{}` defers actions to the response, which selection mode `all` \
+                     doesn't support",
+                    action_set.name
+                ));
+            }
+            let services = with_default_failure_mode(
+                &config.services,
+                action_set.failure_mode.or(config.failure_mode),
+            );
+            let runtime_action_set = Rc::new(RuntimeActionSet::new(action_set, &services)?);
+            match &action_set.route_rule_conditions.hostnames {
+                None => index.insert_host_agnostic(Rc::clone(&runtime_action_set)),
+                Some(hostnames) => {
+                    for hostname in hostnames {
+                        index.insert(hostname, Rc::clone(&runtime_action_set));
+                    }
+                }
             }
         }
 
@@ -19,12 +55,102 @@ impl TryFrom<PluginConfiguration> for ActionSetIndex {
     }
 }
 
+// The services as seen by an action set: those not setting a failure mode get the default one
+fn with_default_failure_mode(
+    services: &HashMap<String, Service>,
+    failure_mode: Option<FailureMode>,
+) -> Cow<'_, HashMap<String, Service>> {
+    let Some(failure_mode) = failure_mode else {
+        return Cow::Borrowed(services);
+    };
+    Cow::Owned(
+        services
+            .iter()
+            .map(|(name, service)| {
+                let mut service = service.clone();
+                service.failure_mode.get_or_insert(failure_mode);
+                (name.clone(), service)
+            })
+            .collect(),
+    )
+}
+
 impl Default for ActionSetIndex {
     fn default() -> Self {
         ActionSetIndex::new()
     }
 }
 
+// Overview of a configuration logged when it is applied, so that operators can spot mistakes
+// such as a misspelled attribute, which would otherwise silently evaluate to null
+pub(crate) struct ConfigSummary {
+    action_sets: usize,
+    services: usize,
+    expressions: usize,
+    unknown_attributes: BTreeSet<String>,
+}
+
+impl From<&PluginConfiguration> for ConfigSummary {
+    fn from(config: &PluginConfiguration) -> Self {
+        let mut sources = Vec::new();
+        let mut static_values = 0;
+        for action_set in config.action_sets.iter() {
+            sources.extend(action_set.route_rule_conditions.predicates.iter());
+            for action in action_set.actions.iter() {
+                sources.extend(action.predicates.iter());
+                for datum in action.data.iter() {
+                    match &datum.item {
+                        DataType::Static(_) => static_values += 1,
+                        DataType::Expression(exp_item) => sources.push(&exp_item.value),
+                    }
+                }
+            }
+        }
+
+        let mut unknown_attributes = BTreeSet::new();
+        for source in sources.iter() {
+            // failing expressions are reported when the configuration gets compiled
+            if let Ok(expression) = Expression::new(source) {
+                unknown_attributes.extend(
+                    expression
+                        .unknown_attributes()
+                        .into_iter()
+                        .map(|path| path.to_string()),
+                );
+            }
+        }
+
+        Self {
+            action_sets: config.action_sets.len(),
+            services: config.services.len(),
+            expressions: sources.len() + static_values,
+            unknown_attributes,
+        }
+    }
+}
+
+impl Display for ConfigSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} action sets, {} services, {} predicates/expressions",
+            self.action_sets, self.services, self.expressions
+        )?;
+        if !self.unknown_attributes.is_empty() {
+            write!(
+                f,
+                ", unknown attributes: {}",
+                self.unknown_attributes
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -103,6 +229,55 @@ mod test {
         assert!(rlp_option.is_none());
     }
 
+    #[test]
+    fn runtime_config_without_hostnames() {
+        let config = r#"{
+            "services": {},
+            "actionSets": [
+            {
+                "name": "some-name",
+                "routeRuleConditions": {
+                    "predicates": ["request.headers['x-env'] == 'canary'"]
+                },
+                "actions": []
+            }]
+        }"#;
+        let serde_res = serde_json::from_str::<PluginConfiguration>(config);
+        if let Err(ref e) = serde_res {
+            eprintln!("{e}");
+        }
+        assert!(serde_res.is_ok());
+
+        let index = ActionSetIndex::try_from(serde_res.expect("That didn't work"))
+            .expect("That didn't work");
+        assert!(index.get_longest_match_action_sets("example.com").is_none());
+        let action_sets = index.get_host_agnostic_action_sets();
+        assert_eq!(action_sets.len(), 1);
+        assert_eq!(action_sets[0].name, "some-name");
+    }
+
+    #[test]
+    fn runtime_config_with_empty_hostnames() {
+        let config = r#"{
+            "services": {},
+            "actionSets": [
+            {
+                "name": "some-name",
+                "routeRuleConditions": {
+                    "hostnames": []
+                },
+                "actions": []
+            }]
+        }"#;
+        let serde_res = serde_json::from_str::<PluginConfiguration>(config);
+        assert!(serde_res.is_ok());
+
+        let index = ActionSetIndex::try_from(serde_res.expect("That didn't work"))
+            .expect("That didn't work");
+        assert!(index.get_longest_match_action_sets("example.com").is_none());
+        assert!(index.get_host_agnostic_action_sets().is_empty());
+    }
+
     #[test]
     fn runtime_config_raises_error_when_action_service_does_not_exist_in_services() {
         let config = r#"{
@@ -142,4 +317,230 @@ mod test {
         let result = ActionSetIndex::try_from(serde_res.expect("That didn't work"));
         assert_eq!(result.err(), Some("Unknown service: unknown".into()));
     }
+
+    #[test]
+    fn runtime_config_caps_the_actions_of_an_action_set() {
+        let config = |max_actions: usize| {
+            format!(
+                r#"{{
+                "services": {{
+                    "limitador": {{
+                        "type": "ratelimit",
+                        "endpoint": "limitador",
+                        "failureMode": "allow"
+                    }}
+                }},
+                "maxActions": {max_actions},
+                "actionSets": [
+                {{
+                    "name": "some-name",
+                    "routeRuleConditions": {{
+                        "hostnames": ["*.example.com"]
+                    }},
+                    "actions": [
+                    {{
+                        "service": "limitador",
+                        "scope": "a"
+                    }},
+                    {{
+                        "service": "limitador",
+                        "scope": "b"
+                    }}]
+                }}]
+            }}"#
+            )
+        };
+
+        let serde_res = serde_json::from_str::<PluginConfiguration>(&config(2));
+        let result = ActionSetIndex::try_from(serde_res.expect("That didn't work"));
+        assert!(result.is_ok());
+
+        let serde_res = serde_json::from_str::<PluginConfiguration>(&config(1));
+        let result = ActionSetIndex::try_from(serde_res.expect("That didn't work"));
+        assert_eq!(
+            result.err(),
+            Some("Action set `some-name` has 2 actions, over the maximum of 1".into())
+        );
+    }
+
+    #[test]
+    fn runtime_config_resolves_the_failure_mode_of_the_actions() {
+        let config = r#"{
+            "services": {
+                "explicit": {
+                    "type": "ratelimit",
+                    "endpoint": "limitador",
+                    "failureMode": "deny"
+                },
+                "implicit": {
+                    "type": "ratelimit",
+                    "endpoint": "limitador"
+                }
+            },
+            "failureMode": "passthrough",
+            "actionSets": [
+            {
+                "name": "with-failure-mode",
+                "routeRuleConditions": {
+                    "hostnames": ["a.example.com"]
+                },
+                "failureMode": "allow",
+                "actions": [
+                {
+                    "service": "implicit",
+                    "scope": "a"
+                },
+                {
+                    "service": "explicit",
+                    "scope": "b"
+                },
+                {
+                    "service": "explicit",
+                    "scope": "c",
+                    "failureMode": "passthrough"
+                }]
+            },
+            {
+                "name": "without-failure-mode",
+                "routeRuleConditions": {
+                    "hostnames": ["b.example.com"]
+                },
+                "actions": [
+                {
+                    "service": "implicit",
+                    "scope": "a"
+                }]
+            }]
+        }"#;
+        let serde_res = serde_json::from_str::<PluginConfiguration>(config);
+        let index = ActionSetIndex::try_from(serde_res.expect("That didn't work"))
+            .expect("This must be a valid configuration");
+        let failure_modes = |host: &str| -> Vec<FailureMode> {
+            index
+                .get_longest_match_action_sets(host)
+                .expect("There must be an action set")[0]
+                .runtime_actions
+                .iter()
+                .map(|action| action.get_failure_mode())
+                .collect()
+        };
+
+        // the action set's over the global one, the service's over the action set's, and the
+        // action's over the service's
+        assert_eq!(
+            failure_modes("a.example.com"),
+            vec![
+                FailureMode::Allow,
+                FailureMode::Deny,
+                FailureMode::Passthrough
+            ]
+        );
+        // the global one, when neither the service nor the action set set one
+        assert_eq!(
+            failure_modes("b.example.com"),
+            vec![FailureMode::Passthrough]
+        );
+    }
+
+    #[test]
+    fn runtime_config_rejects_deferred_actions_when_selecting_all() {
+        let config = |selection_mode: &str| {
+            format!(
+                r#"{{
+                "services": {{
+                    "limitador": {{
+                        "type": "ratelimit",
+                        "endpoint": "limitador"
+                    }}
+                }},
+                "selectionMode": "{selection_mode}",
+                "actionSets": [
+                {{
+                    "name": "some-name",
+                    "routeRuleConditions": {{
+                        "hostnames": ["*.example.com"]
+                    }},
+                    "actions": [
+                    {{
+                        "service": "limitador",
+                        "scope": "a",
+                        "responsePredicates": ["response.code >= 500"]
+                    }}]
+                }}]
+            }}"#
+            )
+        };
+
+        let serde_res = serde_json::from_str::<PluginConfiguration>(&config("first"));
+        let result = ActionSetIndex::try_from(serde_res.expect("That didn't work"));
+        assert!(result.is_ok());
+
+        let serde_res = serde_json::from_str::<PluginConfiguration>(&config("all"));
+        let result = ActionSetIndex::try_from(serde_res.expect("That didn't work"));
+        assert_eq!(
+            result.err(),
+            Some(
+                "Action set `some-name` defers actions to the response, which selection mode \
+                 `all` doesn't support"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn config_summary_lists_unknown_attributes() {
+        let config = r#"{
+            "services": {
+                "limitador": {
+                    "type": "ratelimit",
+                    "endpoint": "limitador",
+                    "failureMode": "allow"
+                }
+            },
+            "actionSets": [
+            {
+                "name": "some-name",
+                "routeRuleConditions": {
+                    "hostnames": ["*.example.com"],
+                    "predicates": [
+                        "request.ur_path == '/admin'",
+                        "request.headers['x-foo'] == 'bar'"
+                    ]
+                },
+                "actions": [
+                {
+                    "service": "limitador",
+                    "scope": "some-scope",
+                    "predicates": [
+                        "auth.identity.anonymous == false"
+                    ],
+                    "data": [
+                    {
+                        "static": {
+                            "key": "a",
+                            "value": "1"
+                        }
+                    },
+                    {
+                        "expression": {
+                            "key": "b",
+                            "value": "source.adress"
+                        }
+                    }]
+                }]
+            }]
+        }"#;
+        let plugin_config =
+            serde_json::from_str::<PluginConfiguration>(config).expect("That didn't work");
+
+        let summary = ConfigSummary::from(&plugin_config);
+        assert_eq!(summary.action_sets, 1);
+        assert_eq!(summary.services, 1);
+        assert_eq!(summary.expressions, 5);
+        assert_eq!(
+            summary.to_string(),
+            "1 action sets, 1 services, 5 predicates/expressions, \
+            unknown attributes: request.ur_path, source.adress"
+        );
+    }
 }
@@ -1,15 +1,20 @@
 pub(crate) mod auth;
 pub(crate) mod rate_limit;
 
-use crate::configuration::{FailureMode, Service, ServiceType};
+use crate::configuration::{
+    CheckRequestField, DenyResponse, FailureMode, OnTimeout, Service, ServiceType,
+};
+use crate::data::{Expression, Predicate};
 use crate::envoy::StatusCode;
 use crate::service::auth::{AUTH_METHOD_NAME, AUTH_SERVICE_NAME};
 use crate::service::rate_limit::{RATELIMIT_METHOD_NAME, RATELIMIT_SERVICE_NAME};
 use crate::service::TracingHeader::{Baggage, Traceparent, Tracestate};
+use cel_interpreter::Value;
+use log::warn;
 use proxy_wasm::types::Bytes;
 use std::cell::OnceCell;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 #[derive(Default, Debug)]
 pub struct GrpcService {
@@ -39,7 +44,33 @@ impl GrpcService {
     }
 
     pub fn get_failure_mode(&self) -> FailureMode {
-        self.service.failure_mode
+        self.service.failure_mode.unwrap_or_default()
+    }
+
+    pub fn get_on_timeout(&self) -> Option<OnTimeout> {
+        self.service.on_timeout
+    }
+
+    pub fn get_max_response_size(&self) -> Option<usize> {
+        self.service.max_response_size
+    }
+
+    pub fn get_retries(&self) -> u32 {
+        self.service.retries.unwrap_or_default()
+    }
+
+    /// Headers added to the response when a failure was let through as FailureMode::Passthrough
+    pub fn degraded_headers(&self) -> Headers {
+        let header = self
+            .service
+            .degraded_header
+            .as_deref()
+            .unwrap_or(DEFAULT_DEGRADED_HEADER);
+        vec![(header.to_string(), "true".to_string())]
+    }
+
+    pub fn get_field_mask(&self) -> Option<&[CheckRequestField]> {
+        self.service.field_mask.as_deref()
     }
 
     fn endpoint(&self) -> &str {
@@ -84,6 +115,7 @@ impl IndexedGrpcRequest {
 }
 
 // GrpcRequest contains the information required to make a Grpc Call
+#[derive(Clone)]
 pub struct GrpcRequest {
     upstream_name: String,
     service_name: String,
@@ -130,7 +162,75 @@ impl GrpcRequest {
     }
 }
 
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+// What's left of a request's deadline, none once past it
+pub fn remaining_budget(deadline: Duration, started: SystemTime, now: SystemTime) -> Duration {
+    deadline.saturating_sub(now.duration_since(started).unwrap_or_default())
+}
+
+// `grpc-timeout` value of a budget: at most 8 digits, in milliseconds as long as they fit
+pub fn grpc_timeout(budget: Duration) -> String {
+    let millis = budget.as_millis();
+    if millis <= 99_999_999 {
+        format!("{millis}m")
+    } else {
+        format!("{}S", budget.as_secs().min(99_999_999))
+    }
+}
+
 pub type Headers = Vec<(String, String)>;
+
+const DEFAULT_DEGRADED_HEADER: &str = "x-kuadrant-degraded";
+
+// The configured reply to the denied requests, its expressions compiled
+#[derive(Debug)]
+pub struct DenyReply {
+    config: DenyResponse,
+    body: Option<Expression>,
+    headers: Vec<(String, Expression)>,
+}
+
+impl DenyReply {
+    pub fn new(config: &DenyResponse) -> Result<Self, String> {
+        let compile = |expression: &String| Expression::new(expression).map_err(|e| e.to_string());
+        Ok(Self {
+            config: config.clone(),
+            body: config.body_expression.as_ref().map(compile).transpose()?,
+            headers: config
+                .header_expressions
+                .iter()
+                .map(|(header, expression)| Ok((header.to_owned(), compile(expression)?)))
+                .collect::<Result<_, String>>()?,
+        })
+    }
+
+    // The value to reply with, unless the expression fails, the static one applying then
+    fn render(expression: &Expression) -> Option<String> {
+        match expression.eval() {
+            Ok(Value::String(s)) => Some((*s).clone()),
+            Ok(Value::Int(n)) => Some(n.to_string()),
+            Ok(Value::UInt(n)) => Some(n.to_string()),
+            Ok(Value::Float(n)) => Some(n.to_string()),
+            Ok(Value::Bool(b)) => Some(b.to_string()),
+            Ok(value) => {
+                warn!("render: {expression:?} is no scalar value: {value:?}");
+                None
+            }
+            Err(e) => {
+                warn!("render: failed to evaluate {expression:?}: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl PartialEq for DenyReply {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
 #[derive(Debug)]
 pub struct GrpcErrResponse {
     status_code: u32,
@@ -147,6 +247,43 @@ impl GrpcErrResponse {
         }
     }
 
+    /// The configured reply, the headers the service added included but for those it sets
+    pub fn new_denied(deny_reply: &DenyReply, service_headers: Headers) -> Self {
+        let config = &deny_reply.config;
+        let mut response_headers: Headers = config
+            .headers
+            .iter()
+            .filter(|(header, _)| !config.header_expressions.contains_key(*header))
+            .map(|(header, value)| (header.to_owned(), value.to_owned()))
+            .collect();
+        response_headers.extend(
+            deny_reply
+                .headers
+                .iter()
+                .filter_map(|(header, expression)| {
+                    DenyReply::render(expression)
+                        .or_else(|| config.headers.get(header).cloned())
+                        .map(|value| (header.to_owned(), value))
+                }),
+        );
+        response_headers.extend(service_headers.into_iter().filter(|(header, _)| {
+            !config
+                .headers
+                .keys()
+                .chain(config.header_expressions.keys())
+                .any(|configured| configured.eq_ignore_ascii_case(header))
+        }));
+        Self {
+            status_code: config.status,
+            response_headers,
+            body: deny_reply
+                .body
+                .as_ref()
+                .and_then(DenyReply::render)
+                .unwrap_or_else(|| config.body.clone()),
+        }
+    }
+
     pub fn new_internal_server_error() -> Self {
         Self {
             status_code: StatusCode::InternalServerError as u32,
@@ -174,6 +311,7 @@ impl GrpcErrResponse {
 #[derive(Debug)]
 pub struct HeaderResolver {
     headers: OnceCell<Vec<(&'static str, Bytes)>>,
+    condition: Option<Rc<Predicate>>,
 }
 
 impl Default for HeaderResolver {
@@ -184,8 +322,15 @@ impl Default for HeaderResolver {
 
 impl HeaderResolver {
     pub fn new() -> Self {
+        Self::new_with_condition(None)
+    }
+
+    /// Only resolves the headers of the requests matching the `condition`, if any, so that
+    /// the trace context of other requests doesn't get propagated
+    pub fn new_with_condition(condition: Option<Rc<Predicate>>) -> Self {
         Self {
             headers: OnceCell::new(),
+            condition,
         }
     }
 
@@ -195,6 +340,16 @@ impl HeaderResolver {
     ) -> &Vec<(&'static str, Bytes)> {
         self.headers.get_or_init(|| {
             let mut headers = Vec::new();
+            if let Some(condition) = &self.condition {
+                match condition.test() {
+                    Ok(true) => {}
+                    Ok(false) => return headers,
+                    Err(err) => {
+                        warn!("failed to evaluate tracing predicate: {err}");
+                        return headers;
+                    }
+                }
+            }
             for header in TracingHeader::all() {
                 if let Some(value) = ctx.get_http_request_header_bytes((*header).as_str()) {
                     headers.push(((*header).as_str(), value));
@@ -266,4 +421,49 @@ mod test {
             },
         )
     }
+
+    #[test]
+    fn read_headers_only_when_condition_applies() {
+        let headers: Vec<(&str, Bytes)> = vec![("traceparent", b"xyz".to_vec())];
+        let mock_host = MockHost::new(headers.iter().cloned().collect::<HashMap<_, _>>());
+
+        let header_resolver = HeaderResolver::new_with_condition(Some(Rc::new(
+            Predicate::new("1 + 1 == 2").expect("This is valid CEL!"),
+        )));
+        assert_eq!(header_resolver.get_with_ctx(&mock_host), &headers);
+
+        let header_resolver = HeaderResolver::new_with_condition(Some(Rc::new(
+            Predicate::new("1 + 1 == 3").expect("This is valid CEL!"),
+        )));
+        assert!(header_resolver.get_with_ctx(&mock_host).is_empty());
+    }
+
+    #[test]
+    fn remaining_budget_of_the_deadline() {
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let deadline = Duration::from_millis(500);
+
+        assert_eq!(
+            remaining_budget(deadline, started, started + Duration::from_millis(200)),
+            Duration::from_millis(300)
+        );
+        assert_eq!(
+            remaining_budget(deadline, started, started + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        // a clock going backwards leaves the whole budget
+        assert_eq!(
+            remaining_budget(deadline, started, started - Duration::from_millis(10)),
+            deadline
+        );
+    }
+
+    #[test]
+    fn grpc_timeout_of_the_budget() {
+        assert_eq!(grpc_timeout(Duration::from_millis(300)), "300m");
+        assert_eq!(grpc_timeout(Duration::from_micros(300_900)), "300m");
+        assert_eq!(grpc_timeout(Duration::ZERO), "0m");
+        assert_eq!(grpc_timeout(Duration::from_secs(100_000)), "100000S");
+        assert_eq!(grpc_timeout(Duration::MAX), "99999999S");
+    }
 }
@@ -1,7 +1,9 @@
 use crate::util::common::wasm_module;
 use crate::util::data;
 use proxy_wasm_test_framework::tester;
-use proxy_wasm_test_framework::types::{Action, BufferType, LogLevel, MapType, ReturnType};
+use proxy_wasm_test_framework::types::{
+    Action, BufferType, LogLevel, MapType, MetricType, ReturnType,
+};
 use serial_test::serial;
 
 pub mod util;
@@ -63,6 +65,9 @@ fn it_auths() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(CONFIG.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -255,6 +260,9 @@ fn it_denies() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(CONFIG.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -484,6 +492,9 @@ fn it_does_not_fold_auth_actions() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(cfg.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -733,3 +744,130 @@ fn it_does_not_fold_auth_actions() {
         .execute_and_expect(ReturnType::Action(Action::Continue))
         .unwrap();
 }
+
+#[test]
+#[serial]
+fn it_only_resolves_the_masked_attributes() {
+    let args = tester::MockSettings {
+        wasm_path: wasm_module(),
+        quiet: false,
+        allow_unexpected: false,
+    };
+    let mut module = tester::mock(args).unwrap();
+
+    module
+        .call_start()
+        .execute_and_expect(ReturnType::None)
+        .unwrap();
+
+    let root_context = 1;
+    let cfg = r#"{
+        "services": {
+            "authorino": {
+                "type": "auth",
+                "endpoint": "authorino-cluster",
+                "failureMode": "deny",
+                "timeout": "5s",
+                "fieldMask": ["host", "path", "source"]
+            }
+        },
+        "actionSets": [
+        {
+            "name": "some-name",
+            "routeRuleConditions": {
+                "hostnames": ["example.com"]
+            },
+            "actions": [
+            {
+                "service": "authorino",
+                "scope": "authconfig-A"
+            }]
+        }]
+    }"#;
+
+    module
+        .call_proxy_on_context_create(root_context, 0)
+        .expect_log(Some(LogLevel::Info), Some("#1 set_root_context"))
+        .execute_and_expect(ReturnType::None)
+        .unwrap();
+
+    module
+        .call_proxy_on_configure(root_context, 0)
+        .expect_log(Some(LogLevel::Info), Some("#1 on_configure"))
+        .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
+        .returning(Some(cfg.as_bytes()))
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
+        .execute_and_expect(ReturnType::Bool(true))
+        .unwrap();
+
+    let http_context = 2;
+    module
+        .call_proxy_on_context_create(http_context, root_context)
+        .expect_log(Some(LogLevel::Debug), Some("#2 create_http_context"))
+        .execute_and_expect(ReturnType::None)
+        .unwrap();
+
+    module
+        .call_proxy_on_request_headers(http_context, 0, false)
+        .expect_log(Some(LogLevel::Debug), Some("#2 on_http_request_headers"))
+        .expect_get_header_map_value(Some(MapType::HttpRequestHeaders), Some(":authority"))
+        .returning(Some("example.com"))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("#2 action_set selected some-name"),
+        )
+        // retrieving only the masked properties for CheckRequest
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("get_property: path: [\"request\", \"host\"]"),
+        )
+        .expect_get_property(Some(vec!["request", "host"]))
+        .returning(Some(data::request::HOST))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("get_property: path: [\"request\", \"path\"]"),
+        )
+        .expect_get_property(Some(vec!["request", "path"]))
+        .returning(Some(data::request::path::ADMIN_TOY))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("get_property: path: [\"source\", \"address\"]"),
+        )
+        .expect_get_property(Some(vec!["source", "address"]))
+        .returning(Some(data::source::ADDRESS))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("get_property: path: [\"source\", \"port\"]"),
+        )
+        .expect_get_property(Some(vec!["source", "port"]))
+        .returning(Some(data::source::port::P_45000))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("handle_operation: SendGrpcRequest"),
+        )
+        // retrieving tracing headers
+        .expect_get_header_map_value(Some(MapType::HttpRequestHeaders), Some("traceparent"))
+        .returning(None)
+        .expect_get_header_map_value(Some(MapType::HttpRequestHeaders), Some("tracestate"))
+        .returning(None)
+        .expect_get_header_map_value(Some(MapType::HttpRequestHeaders), Some("baggage"))
+        .returning(None)
+        .expect_grpc_call(
+            Some("authorino-cluster"),
+            Some("envoy.service.auth.v3.Authorization"),
+            Some("Check"),
+            Some(&[0, 0, 0, 0]),
+            None,
+            Some(5000),
+        )
+        .returning(Ok(42))
+        .expect_log(
+            Some(LogLevel::Debug),
+            Some("handle_operation: AwaitGrpcResponse"),
+        )
+        .execute_and_expect(ReturnType::Action(Action::Pause))
+        .unwrap();
+}
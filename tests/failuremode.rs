@@ -1,6 +1,8 @@
 use crate::util::common::wasm_module;
 use proxy_wasm_test_framework::tester;
-use proxy_wasm_test_framework::types::{Action, BufferType, LogLevel, MapType, ReturnType};
+use proxy_wasm_test_framework::types::{
+    Action, BufferType, LogLevel, MapType, MetricType, ReturnType,
+};
 use serial_test::serial;
 
 pub mod util;
@@ -81,6 +83,9 @@ fn it_runs_next_action_on_failure_when_failuremode_is_allow() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(cfg.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -263,6 +268,9 @@ fn it_stops_on_failure_when_failuremode_is_deny() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(cfg.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
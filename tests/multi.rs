@@ -1,7 +1,9 @@
 use crate::util::common::wasm_module;
 use crate::util::data;
 use proxy_wasm_test_framework::tester;
-use proxy_wasm_test_framework::types::{Action, BufferType, LogLevel, MapType, ReturnType};
+use proxy_wasm_test_framework::types::{
+    Action, BufferType, LogLevel, MapType, MetricType, ReturnType,
+};
 use serial_test::serial;
 
 pub mod util;
@@ -80,6 +82,9 @@ fn it_performs_authenticated_rate_limiting() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(CONFIG.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -298,6 +303,9 @@ fn unauthenticated_does_not_ratelimit() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(CONFIG.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -567,6 +575,9 @@ fn authenticated_one_ratelimit_action_matches() {
         .expect_get_buffer_bytes(Some(BufferType::PluginConfiguration))
         .returning(Some(cfg.as_bytes()))
         .expect_log(Some(LogLevel::Info), None)
+        .expect_log(Some(LogLevel::Info), None)
+        .expect_metric_creation(MetricType::Counter, "kuadrant.config_reloads")
+        .expect_metric_increment("kuadrant.config_reloads", 1)
         .execute_and_expect(ReturnType::Bool(true))
         .unwrap();
 
@@ -57,6 +57,9 @@ pub mod destination {
 pub mod connection {
     pub const ID: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0];
     pub const MTLS: &[u8] = &[0];
+    // 'toystore.com'
+    pub const REQUESTED_SERVER_NAME: &[u8] =
+        &[116, 111, 121, 115, 116, 111, 114, 101, 46, 99, 111, 109];
 }
 
 // Example CheckRequest